@@ -0,0 +1,17 @@
+-- Tables as first created by PostgresMemory::new; IF NOT EXISTS so that
+-- databases set up before migrations existed are adopted as they are.
+CREATE TABLE IF NOT EXISTS messages (
+    id SERIAL PRIMARY KEY,
+    session_id VARCHAR NOT NULL,
+    role VARCHAR NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS session_metadata (
+    session_id VARCHAR NOT NULL,
+    key VARCHAR NOT NULL,
+    value TEXT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (session_id, key)
+);
@@ -0,0 +1,6 @@
+-- Sender and tool calling columns. The tool columns may already exist on
+-- databases created while they were added at startup.
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS user_id VARCHAR,
+    ADD COLUMN IF NOT EXISTS tool_calls TEXT,
+    ADD COLUMN IF NOT EXISTS tool_call_id VARCHAR;
@@ -0,0 +1,2 @@
+-- History is always read per session in creation order.
+CREATE INDEX IF NOT EXISTS idx_messages_session_created ON messages (session_id, created_at);
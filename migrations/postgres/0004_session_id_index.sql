@@ -0,0 +1,2 @@
+-- History pages are addressed by message id within a session.
+CREATE INDEX IF NOT EXISTS idx_messages_session_id ON messages (session_id, id);
@@ -1,55 +1,132 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
+use futures::{StreamExt, stream::BoxStream};
 
 use crate::{
-    llm::{LLMClient, VisionClient, VoiceClient},
-    memory::Memory,
-    persona::PersonaManager,
-    prompt::{Input, Message},
+    context::{self, ContextBuilder},
+    llm::{
+        GenerationOptions, LLMClient, LlmError, TokenStream, VisionClient, VoiceClient,
+        router::ProviderRouter,
+    },
+    memory::{Memory, summary::Summarizer},
+    persona::{
+        Persona, PersonaManager,
+        state::{Exchange, PersonaState, StateTracker},
+        template,
+    },
+    prompt::{Input, Message, MessageContext},
+    tools::ToolRegistry,
 };
 
-pub struct Bot {
+/// Upper bound on model ↔ tool round trips for a single user message.
+const MAX_TOOL_ROUNDS: usize = 5;
+
+/// Newest stored messages read per reply unless configured otherwise.
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// Session metadata key holding the name of the persona bound to a session.
+const PERSONA_KEY: &str = "persona";
+
+/// Reply deltas as delivered to platforms.
+pub type ReplyStream = BoxStream<'static, Result<String>>;
+
+/// A prepared request: the client chosen by the persona, the context and its
+/// sampling parameters.
+struct Turn {
     llm: Arc<dyn LLMClient>,
+    messages: Vec<Message>,
+    options: GenerationOptions,
+    persona_name: String,
+    persona: Arc<Persona>,
+}
+
+impl Turn {
+    /// The exchange to update the persona's state with, if it keeps one. The
+    /// reply is filled in once it is known.
+    fn exchange(&self, input: &str) -> Option<Exchange> {
+        Some(Exchange {
+            persona_name: self.persona_name.clone(),
+            config: self.persona.state.clone()?,
+            llm: self.llm.clone(),
+            input: input.to_string(),
+            reply: String::new(),
+        })
+    }
+}
+
+pub struct Bot {
+    llm: ProviderRouter,
     memory: Option<Arc<dyn Memory>>,
+    summarizer: Option<Arc<Summarizer>>,
+    /// Newest stored messages read per reply.
+    history_limit: usize,
     persona_manager: Arc<PersonaManager>,
+    tools: ToolRegistry,
     vision_client: Option<Arc<dyn VisionClient>>,
     voice_client: Option<Arc<dyn VoiceClient>>,
+    /// Persona bindings when there is no memory to persist them in.
+    persona_bindings: Mutex<HashMap<String, String>>,
+    state: Arc<StateTracker>,
 }
 
 impl Bot {
     pub fn new(
-        llm: Arc<dyn LLMClient>,
+        llm: ProviderRouter,
         memory: Option<Arc<dyn Memory>>,
+        summarizer: Option<Arc<Summarizer>>,
         persona_manager: Arc<PersonaManager>,
+        tools: ToolRegistry,
         vision_client: Option<Arc<dyn VisionClient>>,
         voice_client: Option<Arc<dyn VoiceClient>>,
     ) -> Self {
         Self {
             llm,
+            state: Arc::new(StateTracker::new(memory.clone())),
             memory,
+            summarizer,
+            history_limit: DEFAULT_HISTORY_LIMIT,
             persona_manager,
+            tools,
             vision_client,
             voice_client,
+            persona_bindings: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn get_greeting(&self) -> String {
-        self.persona_manager
-            .get_default_persona()
-            .greeting
-            .clone()
-            .unwrap_or_else(|| "Hello! I am ready.".to_string())
+    /// Reads at most `limit` stored messages per reply.
+    #[must_use]
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    pub async fn get_greeting(&self, session_id: &str, ctx: &MessageContext) -> Result<String> {
+        let (name, persona) = self.persona(session_id).await?;
+        let state = self.persona_state(session_id, &name, &persona).await?;
+        Ok(persona.greeting.as_deref().map_or_else(
+            || "Hello! I am ready.".to_string(),
+            |greeting| template::render(greeting, &template_vars(&persona, ctx, state.as_ref())),
+        ))
     }
 
     pub async fn handle_message(
         &self,
         session_id: &str,
         input: Input,
-        user_id: Option<&str>,
+        ctx: &MessageContext,
     ) -> Result<String> {
         match input {
-            Input::Text(text) => self.handle_text(session_id, &text, user_id).await,
+            Input::Text(text)
+                if let Some(reply) = self.handle_command(session_id, &text, ctx).await? =>
+            {
+                Ok(reply)
+            }
+            Input::Text(text) => self.handle_text(session_id, &text, ctx).await,
             Input::Image(url) => {
                 if let Some(vision) = &self.vision_client {
                     let analysis = vision.analyze_image(&url, "Describe this image").await?;
@@ -61,7 +138,7 @@ impl Bot {
             Input::Audio(data) => {
                 if let Some(voice) = &self.voice_client {
                     let text = voice.speech_to_text(&data).await?;
-                    let response = self.handle_text(session_id, &text, user_id).await?;
+                    let response = self.handle_text(session_id, &text, ctx).await?;
                     Ok(response)
                 } else {
                     Ok("Voice capability not enabled.".to_string())
@@ -78,37 +155,162 @@ impl Bot {
         }
     }
 
-    async fn handle_text(
+    /// Streaming counterpart of [`Bot::handle_message`]. Text replies are yielded
+    /// as token deltas and persisted once the stream completes; other inputs
+    /// yield their full reply as a single delta.
+    pub async fn handle_message_stream(
+        &self,
+        session_id: &str,
+        input: Input,
+        ctx: &MessageContext,
+    ) -> Result<ReplyStream> {
+        match input {
+            Input::Text(text)
+                if let Some(reply) = self.handle_command(session_id, &text, ctx).await? =>
+            {
+                Ok(futures::stream::once(async move { Ok(reply) }).boxed())
+            }
+            Input::Text(text) => self.handle_text_stream(session_id, &text, ctx).await,
+            Input::Audio(data) if let Some(voice) = &self.voice_client => {
+                let text = voice.speech_to_text(&data).await?;
+                self.handle_text_stream(session_id, &text, ctx).await
+            }
+            other => {
+                let reply = self.handle_message(session_id, other, ctx).await?;
+                Ok(futures::stream::once(async move { Ok(reply) }).boxed())
+            }
+        }
+    }
+
+    /// Answers chat commands (`/persona [name]`, `/reset`) without involving
+    /// the LLM. Returns `None` for ordinary messages.
+    async fn handle_command(
         &self,
         session_id: &str,
         input: &str,
-        user_id: Option<&str>,
-    ) -> Result<String> {
-        // 1. Get Persona (Default for now)
-        let persona = self.persona_manager.get_default_persona();
+        ctx: &MessageContext,
+    ) -> Result<Option<String>> {
+        let mut words = input.split_whitespace();
+        match words.next() {
+            Some("/persona") => {}
+            Some("/reset") => return self.reset(session_id, ctx).await.map(Some),
+            _ => return Ok(None),
+        }
 
-        // 2. Save User Message
-        let user_msg = Message::user(input, user_id.map(std::string::ToString::to_string));
+        let current = self.persona_name(session_id).await?;
+        let Some(name) = words.next() else {
+            let mut reply = String::from("Available personas:");
+            for (name, persona) in self.persona_manager.list() {
+                let marker = if name == current { "*" } else { " " };
+                let _ = write!(
+                    reply,
+                    "\n{marker} {name} - {}: {}",
+                    persona.name, persona.description
+                );
+            }
+            reply.push_str("\nUse /persona <name> to switch.");
+            return Ok(Some(reply));
+        };
+
+        let Some(persona) = self.persona_manager.get(name) else {
+            return Ok(Some(format!(
+                "Unknown persona '{name}'. Use /persona to list the available ones."
+            )));
+        };
 
         if let Some(mem) = &self.memory {
-            mem.add_message(session_id, user_msg.clone()).await?;
+            mem.set_metadata(session_id, PERSONA_KEY, name).await?;
+        } else {
+            self.persona_bindings
+                .lock()
+                .expect("persona bindings lock poisoned")
+                .insert(session_id.to_string(), name.to_string());
         }
 
-        // 3. Build Context (Messages)
-        let mut messages = Vec::new();
+        let state = self.persona_state(session_id, name, &persona).await?;
+        Ok(Some(persona.greeting.as_deref().map_or_else(
+            || format!("Switched to {}.", persona.name),
+            |greeting| template::render(greeting, &template_vars(&persona, ctx, state.as_ref())),
+        )))
+    }
 
-        // System Prompt (from Persona)
-        messages.push(Message::system(&persona.system_prompt));
+    /// Forgets the session's history, summary, persona and state, then greets
+    /// as the default persona.
+    async fn reset(&self, session_id: &str, ctx: &MessageContext) -> Result<String> {
+        // A summary or state still being worked out belongs to the old conversation
+        if let Some(summarizer) = &self.summarizer {
+            summarizer.abort(session_id);
+        }
+        self.state.abort(session_id);
 
-        // History
         if let Some(mem) = &self.memory {
-            let history = mem.get_history(session_id).await?;
-            messages.extend(history);
+            mem.clear_session(session_id).await?;
         } else {
-            messages.push(user_msg.clone());
+            self.persona_bindings
+                .lock()
+                .expect("persona bindings lock poisoned")
+                .remove(session_id);
+            self.state.clear_local(session_id);
         }
+        self.get_greeting(session_id, ctx).await
+    }
 
-        let response_text = self.llm.chat(&messages).await?;
+    /// Name of the persona bound to the session, or the default one.
+    async fn persona_name(&self, session_id: &str) -> Result<String> {
+        let bound = if let Some(mem) = &self.memory {
+            mem.get_metadata(session_id, PERSONA_KEY).await?
+        } else {
+            self.persona_bindings
+                .lock()
+                .expect("persona bindings lock poisoned")
+                .get(session_id)
+                .cloned()
+        };
+
+        // A binding to a persona that has since been removed falls back to the default
+        Ok(bound
+            .filter(|name| self.persona_manager.get(name).is_some())
+            .unwrap_or_else(|| self.persona_manager.default_name()))
+    }
+
+    async fn persona(&self, session_id: &str) -> Result<(String, Arc<Persona>)> {
+        let name = self.persona_name(session_id).await?;
+        match self.persona_manager.get(&name) {
+            Some(persona) => Ok((name, persona)),
+            None => Ok((
+                self.persona_manager.default_name(),
+                self.persona_manager.get_default_persona(),
+            )),
+        }
+    }
+
+    /// State of the persona in the session, for personas that keep one.
+    async fn persona_state(
+        &self,
+        session_id: &str,
+        name: &str,
+        persona: &Persona,
+    ) -> Result<Option<PersonaState>> {
+        match &persona.state {
+            Some(config) => Ok(Some(self.state.load(session_id, name, config).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn handle_text(
+        &self,
+        session_id: &str,
+        input: &str,
+        ctx: &MessageContext,
+    ) -> Result<String> {
+        let turn = self.build_context(session_id, input, ctx).await?;
+        let exchange = turn.exchange(input);
+
+        let response_text = if self.tools.is_empty() {
+            chat_fitting(&turn.llm, &turn.messages, &turn.options).await?
+        } else {
+            self.run_tool_loop(session_id, turn).await?
+        };
 
         // 4. Save Assistant Message
         if let Some(mem) = &self.memory {
@@ -116,6 +318,322 @@ impl Bot {
             mem.add_message(session_id, bot_msg).await?;
         }
 
+        // 5. Let the exchange move the persona's mood and affection
+        if let Some(mut exchange) = exchange {
+            exchange.reply.clone_from(&response_text);
+            self.state.update(session_id, exchange).await?;
+        }
+
         Ok(response_text)
     }
+
+    async fn handle_text_stream(
+        &self,
+        session_id: &str,
+        input: &str,
+        ctx: &MessageContext,
+    ) -> Result<ReplyStream> {
+        // Tool rounds need the complete reply to see the calls, so the final
+        // answer is delivered in one piece.
+        if !self.tools.is_empty() {
+            let reply = self.handle_text(session_id, input, ctx).await?;
+            return Ok(futures::stream::once(async move { Ok(reply) }).boxed());
+        }
+
+        let turn = self.build_context(session_id, input, ctx).await?;
+        let exchange = turn.exchange(input);
+        let Turn {
+            llm,
+            messages,
+            options,
+            ..
+        } = turn;
+
+        let stream = match llm.chat_stream(&messages, &options).await {
+            Err(LlmError::ContextLength { .. }) => {
+                tracing::warn!("Prompt exceeded the context window, retrying with less history");
+                llm.chat_stream(&context::drop_oldest_half(&messages), &options)
+                    .await?
+            }
+            other => other?,
+        };
+
+        // 4. and 5. once the stream has been fully consumed
+        let memory = self.memory.clone();
+        let tracker = self.state.clone();
+        let session_id = session_id.to_string();
+        Ok(on_completion(stream, move |reply| async move {
+            if let Some(mem) = memory {
+                mem.add_message(&session_id, Message::assistant(&reply))
+                    .await?;
+            }
+            if let Some(mut exchange) = exchange {
+                exchange.reply = reply;
+                tracker.update(&session_id, exchange).await?;
+            }
+            Ok(())
+        }))
+    }
+
+    /// Lets the model call tools until it produces a final answer. Tool calls
+    /// and results are persisted so later turns see what was looked up.
+    async fn run_tool_loop(&self, session_id: &str, turn: Turn) -> Result<String> {
+        let Turn {
+            llm,
+            mut messages,
+            options,
+            ..
+        } = turn;
+        let definitions = self.tools.definitions();
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let reply = llm
+                .chat_with_tools(&messages, &definitions, &options)
+                .await?;
+            let calls = reply.requested_tool_calls().to_vec();
+            if calls.is_empty() {
+                return Ok(reply.content);
+            }
+
+            self.remember(session_id, &reply).await?;
+            messages.push(reply);
+
+            for call in &calls {
+                tracing::debug!(
+                    "Calling tool {}({})",
+                    call.function.name,
+                    call.function.arguments
+                );
+                let result = self.tools.invoke(call).await;
+                self.remember(session_id, &result).await?;
+                messages.push(result);
+            }
+        }
+
+        // Out of rounds: ask for an answer with what has been gathered so far.
+        chat_fitting(&llm, &messages, &options).await
+    }
+
+    async fn remember(&self, session_id: &str, message: &Message) -> Result<()> {
+        if let Some(mem) = &self.memory {
+            mem.add_message(session_id, message.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Saves the user message and assembles the messages sent to the LLM,
+    /// along with the persona's provider and sampling parameters.
+    async fn build_context(
+        &self,
+        session_id: &str,
+        input: &str,
+        ctx: &MessageContext,
+    ) -> Result<Turn> {
+        // 1. Get Persona bound to the session, and the client it asks for
+        let (persona_name, persona) = self.persona(session_id).await?;
+        let llm = self.llm.route(persona.provider.as_deref());
+        let mut options = persona.generation_options();
+        // A model belongs to one provider; the default chain would send it to
+        // every provider in turn
+        if !persona
+            .provider
+            .as_deref()
+            .is_some_and(|provider| self.llm.is_configured(provider))
+        {
+            options.model = None;
+        }
+
+        // 2. Save User Message
+        let user_msg = Message::user(input, ctx.user_id.clone());
+
+        if let Some(mem) = &self.memory {
+            mem.add_message(session_id, user_msg.clone()).await?;
+        }
+
+        // 3. Build Context (Messages), trimmed to the model's token budget
+        let mut budget = llm.token_budget();
+        if let Some(max_tokens) = options.max_tokens {
+            // A capped reply leaves the rest of the window to the prompt
+            budget.reserved_output = usize::try_from(max_tokens)?;
+        }
+        let mut context = ContextBuilder::new(budget);
+
+        // System Prompt (from Persona), with template variables filled in
+        let state = self
+            .persona_state(session_id, &persona_name, &persona)
+            .await?;
+        let vars = template_vars(&persona, ctx, state.as_ref());
+        let mut system_prompt = template::render(&persona.system_prompt, &vars);
+        // Mood-specific instructions, so the persona reacts to its state
+        if let (Some(config), Some(state)) = (&persona.state, &state)
+            && let Some(mood_prompt) = config.mood_prompt(state)
+        {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&template::render(mood_prompt, &vars));
+        }
+        context.pin(Message::system(&system_prompt));
+
+        // Few-shot examples of the persona's tone, dropped when space is short
+        context.examples(persona.examples.iter().map(|example| {
+            (
+                Message::user(&template::render(&example.user, &vars), None),
+                Message::assistant(&template::render(&example.assistant, &vars)),
+            )
+        }));
+
+        // History, with older turns condensed into a running summary
+        let (summary, history) = if let Some(mem) = &self.memory {
+            match &self.summarizer {
+                Some(summarizer) => {
+                    summarizer
+                        .prepare(mem, session_id, self.history_limit)
+                        .await?
+                }
+                None => (None, mem.get_history(session_id, self.history_limit).await?),
+            }
+        } else {
+            (None, vec![user_msg])
+        };
+
+        // Lorebook entries mentioned in the recent messages
+        if let Some(lorebook) = &persona.lorebook {
+            let lore = lorebook.activate(&history);
+            if !lore.is_empty() {
+                tracing::debug!("Injecting {} lorebook entries", lore.len());
+                let lore: Vec<String> = lore
+                    .into_iter()
+                    .map(|content| template::render(content, &vars))
+                    .collect();
+                context.pin(Message::system(&lore.join("\n\n")));
+            }
+        }
+
+        if let Some(summary) = summary {
+            context.pin(summary);
+        }
+        context.history(history);
+
+        Ok(Turn {
+            llm,
+            messages: context.build(),
+            options,
+            persona_name,
+            persona,
+        })
+    }
+}
+
+/// Calls the LLM, retrying once with half of the history when the provider
+/// reports that the prompt exceeds its context window despite the budget.
+async fn chat_fitting(
+    llm: &Arc<dyn LLMClient>,
+    messages: &[Message],
+    options: &GenerationOptions,
+) -> Result<String> {
+    match llm.chat(messages, options).await {
+        Err(LlmError::ContextLength { .. }) => {
+            tracing::warn!("Prompt exceeded the context window, retrying with less history");
+            Ok(llm
+                .chat(&context::drop_oldest_half(messages), options)
+                .await?)
+        }
+        other => Ok(other?),
+    }
+}
+
+/// Values for the `{{...}}` placeholders of persona prompts and greetings.
+fn template_vars(
+    persona: &Persona,
+    ctx: &MessageContext,
+    state: Option<&PersonaState>,
+) -> HashMap<String, String> {
+    let mut vars = HashMap::from([
+        (
+            "user_name".to_string(),
+            ctx.user_name.clone().unwrap_or_default(),
+        ),
+        (
+            "now".to_string(),
+            chrono::Local::now()
+                .format("%Y-%m-%d %H:%M (%A)")
+                .to_string(),
+        ),
+        ("platform".to_string(), ctx.platform.to_string()),
+        (
+            "group_name".to_string(),
+            ctx.group_name.clone().unwrap_or_default(),
+        ),
+        ("persona.name".to_string(), persona.name.clone()),
+    ]);
+    if let Some(state) = state {
+        vars.insert("state.mood".to_string(), state.mood.clone());
+        vars.insert("state.affection".to_string(), state.affection.to_string());
+        for (key, value) in &state.custom {
+            vars.insert(format!("state.{key}"), value.clone());
+        }
+    }
+    vars
+}
+
+/// Forwards every delta of `stream` and hands the concatenated reply to `done`
+/// when the stream ends. Nothing is done for a failed stream.
+fn on_completion<F, Fut>(stream: TokenStream, done: F) -> ReplyStream
+where
+    F: FnOnce(String) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    let state = (stream, String::new(), Some(done));
+
+    futures::stream::unfold(state, |(mut stream, mut reply, mut done)| async move {
+        match stream.next().await {
+            Some(Ok(delta)) => {
+                reply.push_str(&delta);
+                Some((Ok(delta), (stream, reply, done)))
+            }
+            Some(Err(e)) => Some((Err(e.into()), (stream, reply, None))),
+            None => {
+                let done = done.take()?;
+                match done(std::mem::take(&mut reply)).await {
+                    Ok(()) => None,
+                    Err(e) => Some((Err(e), (stream, reply, None))),
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_renders_into_prompts() {
+        let persona = Persona {
+            name: "Mia".to_string(),
+            ..Persona::default()
+        };
+        let mut ctx = MessageContext::new("terminal");
+        ctx.user_name = Some("Alice".to_string());
+        let state = PersonaState {
+            mood: "shy".to_string(),
+            affection: -3,
+            custom: [("nickname".to_string(), "Ally".to_string())].into(),
+        };
+        let prompt = "{{persona.name}} feels {{state.mood}} ({{state.affection}}) \
+                      about {{state.nickname|you}}, {{user_name}}.";
+
+        let vars = template_vars(&persona, &ctx, Some(&state));
+        assert_eq!(
+            template::render(prompt, &vars),
+            "Mia feels shy (-3) about Ally, Alice."
+        );
+
+        // Without state tracking the placeholders render empty
+        let vars = template_vars(&persona, &ctx, None);
+        assert_eq!(
+            template::render("{{persona.name}} [{{state.mood}}]", &vars),
+            "Mia []"
+        );
+    }
 }
@@ -0,0 +1,715 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    context::TokenBudget,
+    llm::{deepseek, doubao, fallback::BreakerPolicy, grok, http::HttpPolicy},
+    tools,
+};
+
+/// Config file used when neither `--config` nor `CHATBOT_CONFIG` is given.
+const DEFAULT_PATH: &str = "chatbot.toml";
+
+/// Providers configured by an `[llm.<name>]` section.
+const PROVIDER_SECTIONS: [&str; 4] = ["deepseek", "doubao", "grok", "openai"];
+
+/// Complete bot configuration, loaded and validated once at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub llm: LlmConfig,
+    pub memory: MemoryConfig,
+    /// Newest stored messages read per reply.
+    pub history_limit: usize,
+    pub summary: SummaryConfig,
+    pub persona: PersonaConfig,
+    /// Built-in tools the model may call.
+    pub tools: Vec<String>,
+    pub platform: PlatformConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmConfig {
+    /// Providers in fallback order; never empty.
+    pub providers: Vec<ProviderConfig>,
+    /// Providers with an `[llm.<name>]` section that are not in the fallback
+    /// chain, reachable only by personas naming them.
+    pub extra: Vec<ProviderConfig>,
+    pub http: HttpPolicy,
+    pub circuit: BreakerPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProviderConfig {
+    Mock,
+    DeepSeek(EndpointConfig),
+    Doubao(DoubaoConfig),
+    Grok(EndpointConfig),
+    OpenAi(EndpointConfig),
+}
+
+impl ProviderConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mock => "mock",
+            Self::DeepSeek(_) => "deepseek",
+            Self::Doubao(_) => "doubao",
+            Self::Grok(_) => "grok",
+            Self::OpenAi(_) => "openai",
+        }
+    }
+}
+
+/// Connection settings of an `OpenAI`-compatible chat endpoint.
+#[derive(Debug, Clone)]
+pub struct EndpointConfig {
+    pub api_key: Option<String>,
+    pub base_url: String,
+    pub model: String,
+    pub budget: TokenBudget,
+    /// Headers sent with every request.
+    pub extra_headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoubaoConfig {
+    pub chat: EndpointConfig,
+    pub vision_model: Option<String>,
+    pub asr_model: Option<String>,
+    pub tts_model: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MemoryConfig {
+    None,
+    /// Newest `capacity` messages per session, kept in process.
+    InMemory {
+        capacity: usize,
+        /// JSONL file written on shutdown and read back on startup.
+        snapshot: Option<PathBuf>,
+    },
+    Postgres {
+        url: String,
+    },
+    Redis {
+        url: String,
+        /// Messages kept per session; older ones are trimmed. `0` keeps all.
+        max_messages: usize,
+    },
+    /// Database file, created if missing.
+    Sqlite {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SummaryConfig {
+    /// Unsummarized messages allowed before older ones are condensed; `0` disables it.
+    pub threshold: usize,
+    pub keep_recent: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PersonaConfig {
+    pub dir: String,
+    pub default: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum PlatformConfig {
+    Terminal,
+    OneBot { ws_url: String },
+}
+
+impl Config {
+    /// Loads `path`, or the file named by `CHATBOT_CONFIG`, or `chatbot.toml`
+    /// if it exists, then applies environment variable overrides. Every missing
+    /// or invalid setting is reported in a single error.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let (path, mut loader) = Loader::open(path)?;
+        let config = loader.config();
+        loader.finish(path, config)
+    }
+}
+
+impl PersonaConfig {
+    /// Loads only the persona settings, from the same sources as
+    /// [`Config::load`], so personas can be checked without the rest.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let (path, mut loader) = Loader::open(path)?;
+        let config = loader.persona();
+        loader.finish(path, config)
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let content = fs::read_to_string(path)
+        .context(format!("Failed to read config file: {}", path.display()))?;
+    content
+        .parse()
+        .context(format!("Failed to parse config file: {}", path.display()))
+}
+
+/// Provider-specific defaults for an [`EndpointConfig`].
+struct EndpointDefaults {
+    base_url: Option<&'static str>,
+    model: Option<&'static str>,
+    budget: TokenBudget,
+    requires_api_key: bool,
+}
+
+/// Reads settings from the file table, with environment variables taking
+/// precedence, and collects every problem instead of stopping at the first.
+struct Loader {
+    file: toml::Table,
+    /// The process environment, read once.
+    vars: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    /// Reads `path`, or the file named by `CHATBOT_CONFIG`, or `chatbot.toml`
+    /// if it exists; returns the path used, if any.
+    fn open(path: Option<PathBuf>) -> Result<(Option<PathBuf>, Self)> {
+        let path = path.or_else(|| env::var_os("CHATBOT_CONFIG").map(PathBuf::from));
+        let file = match &path {
+            Some(path) => read_table(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => read_table(Path::new(DEFAULT_PATH))?,
+            None => toml::Table::new(),
+        };
+        let loader = Self {
+            file,
+            // Values that are not unicode are treated as unset
+            vars: env::vars_os()
+                .filter_map(|(key, value)| {
+                    Some((key.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+            errors: Vec::new(),
+        };
+        Ok((path, loader))
+    }
+
+    /// `value` if no problem was found, else all of them in one error.
+    fn finish<T>(self, path: Option<PathBuf>, value: T) -> Result<T> {
+        if self.errors.is_empty() {
+            Ok(value)
+        } else {
+            let source = path.map_or_else(|| DEFAULT_PATH.to_string(), |p| p.display().to_string());
+            anyhow::bail!(
+                "Invalid configuration ({source} and environment):\n  - {}",
+                self.errors.join("\n  - ")
+            )
+        }
+    }
+
+    fn config(&mut self) -> Config {
+        let summary = SummaryConfig {
+            threshold: self.parse("summary.threshold", "SUMMARY_THRESHOLD", 40),
+            keep_recent: self.parse("summary.keep_recent", "SUMMARY_KEEP_RECENT", 10),
+        };
+        let memory = self.memory();
+        Config {
+            llm: self.llm(),
+            history_limit: self.history_limit(&memory, &summary),
+            memory,
+            summary,
+            persona: self.persona(),
+            tools: self.tools(),
+            platform: self.platform(),
+        }
+    }
+
+    fn persona(&mut self) -> PersonaConfig {
+        PersonaConfig {
+            dir: self
+                .string("persona.dir", "PERSONA_DIR")
+                .unwrap_or_else(|| "avatars".to_string()),
+            default: self
+                .string("persona.default", "DEFAULT_PERSONA")
+                .unwrap_or_else(|| "default".to_string()),
+        }
+    }
+
+    fn llm(&mut self) -> LlmConfig {
+        // LLM_PROVIDERS=deepseek,grok,doubao builds a fallback chain; a single
+        // LLM_PROVIDER is still accepted.
+        let env_key = if self.env_value("LLM_PROVIDERS").is_some() {
+            "LLM_PROVIDERS"
+        } else {
+            "LLM_PROVIDER"
+        };
+        let mut names = self.list("llm.providers", env_key);
+        if names.is_empty() {
+            names.push("mock".to_string());
+        }
+
+        let names: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+        let providers = names
+            .iter()
+            .filter_map(|name| self.provider(name))
+            .collect();
+        let extra_names: Vec<&str> = PROVIDER_SECTIONS
+            .into_iter()
+            .filter(|name| !names.iter().any(|n| n == name))
+            .filter(|name| self.lookup(&format!("llm.{name}")).is_some())
+            .collect();
+        let extra = extra_names
+            .into_iter()
+            .filter_map(|name| self.provider(name))
+            .collect();
+
+        let http = HttpPolicy::default();
+        let max_attempts: u32 = self.parse(
+            "llm.http.max_attempts",
+            "LLM_MAX_ATTEMPTS",
+            http.max_attempts,
+        );
+        let circuit = BreakerPolicy::default();
+        let failure_threshold: u32 = self.parse(
+            "llm.circuit.failures",
+            "LLM_CIRCUIT_FAILURES",
+            circuit.failure_threshold,
+        );
+
+        LlmConfig {
+            providers,
+            extra,
+            http: HttpPolicy {
+                connect_timeout: Duration::from_secs(self.parse(
+                    "llm.http.connect_timeout_secs",
+                    "LLM_CONNECT_TIMEOUT_SECS",
+                    http.connect_timeout.as_secs(),
+                )),
+                read_timeout: Duration::from_secs(self.parse(
+                    "llm.http.read_timeout_secs",
+                    "LLM_READ_TIMEOUT_SECS",
+                    http.read_timeout.as_secs(),
+                )),
+                max_attempts: max_attempts.max(1),
+                base_delay: self.millis(
+                    "llm.http.retry_base_ms",
+                    "LLM_RETRY_BASE_MS",
+                    http.base_delay,
+                ),
+                max_delay: self.millis("llm.http.retry_max_ms", "LLM_RETRY_MAX_MS", http.max_delay),
+            },
+            circuit: BreakerPolicy {
+                failure_threshold: failure_threshold.max(1),
+                cooldown: Duration::from_secs(self.parse(
+                    "llm.circuit.cooldown_secs",
+                    "LLM_CIRCUIT_COOLDOWN_SECS",
+                    circuit.cooldown.as_secs(),
+                )),
+            },
+        }
+    }
+
+    fn provider(&mut self, name: &str) -> Option<ProviderConfig> {
+        let provider = match name {
+            "mock" => ProviderConfig::Mock,
+            "deepseek" => ProviderConfig::DeepSeek(self.endpoint(
+                "deepseek",
+                &EndpointDefaults {
+                    base_url: Some(deepseek::DEFAULT_BASE_URL),
+                    model: Some(deepseek::DEFAULT_MODEL),
+                    budget: deepseek::DEFAULT_BUDGET,
+                    requires_api_key: true,
+                },
+            )),
+            "doubao" => ProviderConfig::Doubao(DoubaoConfig {
+                // The model is the Ark endpoint ID of the chat model
+                chat: self.endpoint(
+                    "doubao",
+                    &EndpointDefaults {
+                        base_url: Some(doubao::DEFAULT_BASE_URL),
+                        model: None,
+                        budget: doubao::DEFAULT_BUDGET,
+                        requires_api_key: true,
+                    },
+                ),
+                vision_model: self.string("llm.doubao.vision_model", "DOUBAO_VISION_MODEL"),
+                asr_model: self.string("llm.doubao.asr_model", "DOUBAO_ASR_MODEL"),
+                tts_model: self.string("llm.doubao.tts_model", "DOUBAO_TTS_MODEL"),
+            }),
+            "grok" => ProviderConfig::Grok(self.endpoint(
+                "grok",
+                &EndpointDefaults {
+                    base_url: Some(grok::DEFAULT_BASE_URL),
+                    model: Some(grok::DEFAULT_MODEL),
+                    budget: grok::DEFAULT_BUDGET,
+                    requires_api_key: true,
+                },
+            )),
+            "openai" => ProviderConfig::OpenAi(self.endpoint(
+                "openai",
+                &EndpointDefaults {
+                    base_url: None,
+                    model: None,
+                    budget: TokenBudget::default(),
+                    requires_api_key: false,
+                },
+            )),
+            _ => {
+                self.errors.push(format!(
+                    "unknown provider '{name}' in `llm.providers` (expected mock, deepseek, doubao, grok or openai)"
+                ));
+                return None;
+            }
+        };
+        Some(provider)
+    }
+
+    /// Reads the `[llm.<name>]` section, overridden by `<NAME>_*` variables.
+    fn endpoint(&mut self, name: &str, defaults: &EndpointDefaults) -> EndpointConfig {
+        let section = format!("llm.{name}");
+        let prefix = name.to_uppercase();
+        let key = |field: &str| {
+            (
+                format!("{section}.{field}"),
+                format!("{prefix}_{field}").to_uppercase(),
+            )
+        };
+
+        let (path, env_key) = key("api_key");
+        let api_key = if defaults.requires_api_key {
+            Some(self.required(&path, &env_key))
+        } else {
+            self.string(&path, &env_key)
+        };
+
+        let (path, env_key) = key("base_url");
+        let base_url = match defaults.base_url {
+            Some(default) => self
+                .string(&path, &env_key)
+                .unwrap_or_else(|| default.to_string()),
+            None => self.required(&path, &env_key),
+        };
+
+        let (path, env_key) = key("model");
+        let model = match defaults.model {
+            Some(default) => self
+                .string(&path, &env_key)
+                .unwrap_or_else(|| default.to_string()),
+            None => self.required(&path, &env_key),
+        };
+
+        let (path, env_key) = key("context_window");
+        let context_window = self.parse(&path, &env_key, defaults.budget.context_window);
+        let (path, env_key) = key("reserved_output_tokens");
+        let reserved_output = self.parse(&path, &env_key, defaults.budget.reserved_output);
+
+        let (path, env_key) = key("extra_headers");
+        let extra_headers = self.headers(&path, &env_key);
+
+        EndpointConfig {
+            api_key,
+            base_url,
+            model,
+            budget: TokenBudget::new(context_window, reserved_output),
+            extra_headers,
+        }
+    }
+
+    fn memory(&mut self) -> MemoryConfig {
+        let kind = self
+            .string("memory.type", "MEMORY_TYPE")
+            .unwrap_or_else(|| "in_memory".to_string());
+        match kind.as_str() {
+            "none" => MemoryConfig::None,
+            "in_memory" => MemoryConfig::InMemory {
+                capacity: self.capacity(),
+                snapshot: self
+                    .string("memory.in_memory.snapshot", "MEMORY_SNAPSHOT")
+                    .map(PathBuf::from),
+            },
+            "postgres" => MemoryConfig::Postgres {
+                url: self.required("memory.postgres.url", "DATABASE_URL"),
+            },
+            "redis" => MemoryConfig::Redis {
+                url: self
+                    .string("memory.redis.url", "REDIS_URL")
+                    .unwrap_or_else(|| "redis://127.0.0.1/".to_string()),
+                max_messages: self.parse("memory.redis.max_messages", "REDIS_MAX_MESSAGES", 1000),
+            },
+            "sqlite" => MemoryConfig::Sqlite {
+                path: self
+                    .string("memory.sqlite.path", "SQLITE_PATH")
+                    .map_or_else(|| PathBuf::from("chatbot.db"), PathBuf::from),
+            },
+            other => {
+                self.errors.push(format!(
+                    "unknown memory type '{other}' in `memory.type` (expected none, in_memory, postgres, redis or sqlite)"
+                ));
+                MemoryConfig::None
+            }
+        }
+    }
+
+    fn capacity(&mut self) -> usize {
+        let capacity = self.parse("memory.in_memory.capacity", "MEMORY_CAPACITY", 200);
+        if capacity == 0 {
+            // Every message would be dropped as soon as it is stored
+            self.errors.push(
+                "`memory.in_memory.capacity` (MEMORY_CAPACITY) must be at least 1".to_string(),
+            );
+        }
+        capacity
+    }
+
+    fn history_limit(&mut self, memory: &MemoryConfig, summary: &SummaryConfig) -> usize {
+        let limit = self.parse("memory.history_limit", "HISTORY_LIMIT", 100);
+        if limit == 0 {
+            self.errors
+                .push("`memory.history_limit` (HISTORY_LIMIT) must be at least 1".to_string());
+        } else if !matches!(memory, MemoryConfig::None)
+            && summary.threshold > 0
+            && limit <= summary.threshold
+        {
+            // Only messages within the limit can be folded into the summary
+            self.errors.push(format!(
+                "`memory.history_limit` (HISTORY_LIMIT) must be greater than `summary.threshold` ({}), or the summary never updates",
+                summary.threshold
+            ));
+        }
+        limit
+    }
+
+    fn tools(&mut self) -> Vec<String> {
+        let names = self.list("tools.enabled", "TOOLS");
+        for name in &names {
+            if !tools::BUILTIN_TOOLS.contains(&name.as_str()) {
+                self.errors.push(format!(
+                    "unknown tool '{name}' in `tools.enabled` (expected one of: {})",
+                    tools::BUILTIN_TOOLS.join(", ")
+                ));
+            }
+        }
+        names
+    }
+
+    fn platform(&mut self) -> PlatformConfig {
+        let kind = self
+            .string("platform.type", "PLATFORM")
+            .unwrap_or_else(|| "terminal".to_string());
+        match kind.as_str() {
+            "terminal" => PlatformConfig::Terminal,
+            "onebot" => PlatformConfig::OneBot {
+                ws_url: self
+                    .string("platform.onebot.ws_url", "ONEBOT_WS_URL")
+                    .unwrap_or_else(|| "ws://127.0.0.1:6700".to_string()),
+            },
+            other => {
+                self.errors.push(format!(
+                    "unknown platform '{other}' in `platform.type` (expected terminal or onebot)"
+                ));
+                PlatformConfig::Terminal
+            }
+        }
+    }
+
+    /// Value of `env_key` if set, otherwise the file value at the dotted `path`.
+    fn string(&mut self, path: &str, env_key: &str) -> Option<String> {
+        if let Some(value) = self.env_value(env_key) {
+            return Some(value);
+        }
+        match self.lookup(path)? {
+            toml::Value::String(s) if s.trim().is_empty() => None,
+            toml::Value::String(s) => Some(s),
+            toml::Value::Integer(i) => Some(i.to_string()),
+            toml::Value::Float(f) => Some(f.to_string()),
+            toml::Value::Boolean(b) => Some(b.to_string()),
+            _ => {
+                self.errors.push(format!("`{path}` must be a single value"));
+                None
+            }
+        }
+    }
+
+    fn required(&mut self, path: &str, env_key: &str) -> String {
+        self.string(path, env_key).unwrap_or_else(|| {
+            self.errors.push(format!(
+                "missing `{path}` (or {env_key} in the environment)"
+            ));
+            String::new()
+        })
+    }
+
+    fn parse<T: FromStr>(&mut self, path: &str, env_key: &str, default: T) -> T {
+        let Some(raw) = self.string(path, env_key) else {
+            return default;
+        };
+        raw.trim().parse().unwrap_or_else(|_| {
+            self.errors.push(format!(
+                "`{path}` ({env_key}) must be a non-negative integer, got '{raw}'"
+            ));
+            default
+        })
+    }
+
+    fn millis(&mut self, path: &str, env_key: &str, default: Duration) -> Duration {
+        let default = u64::try_from(default.as_millis()).unwrap_or(u64::MAX);
+        Duration::from_millis(self.parse(path, env_key, default))
+    }
+
+    /// A list given as a TOML array of strings, or as a comma-separated string.
+    fn list(&mut self, path: &str, env_key: &str) -> Vec<String> {
+        let raw = if self.env_value(env_key).is_none()
+            && let Some(toml::Value::Array(items)) = self.lookup(path)
+        {
+            let mut names = Vec::new();
+            for item in items {
+                match item {
+                    toml::Value::String(s) => names.push(s),
+                    other => self
+                        .errors
+                        .push(format!("`{path}` must contain strings, got {other}")),
+                }
+            }
+            names.join(",")
+        } else {
+            self.string(path, env_key).unwrap_or_default()
+        };
+
+        raw.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Headers given as a TOML table, or as `Name: value; Name2: value2`.
+    fn headers(&mut self, path: &str, env_key: &str) -> Vec<(String, String)> {
+        if self.env_value(env_key).is_none()
+            && let Some(toml::Value::Table(table)) = self.lookup(path)
+        {
+            let mut headers = Vec::new();
+            for (name, value) in table {
+                match value {
+                    toml::Value::String(value) => headers.push((name, value)),
+                    other => self
+                        .errors
+                        .push(format!("`{path}.{name}` must be a string, got {other}")),
+                }
+            }
+            return headers;
+        }
+
+        let raw = self.string(path, env_key).unwrap_or_default();
+        let mut headers = Vec::new();
+        for header in raw.split(';').filter(|h| !h.trim().is_empty()) {
+            match header.split_once(':') {
+                Some((name, value)) => {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+                None => self
+                    .errors
+                    .push(format!("invalid header '{header}' in `{path}` ({env_key})")),
+            }
+        }
+        headers
+    }
+
+    /// Environment variable, treating an empty value as unset.
+    fn env_value(&self, key: &str) -> Option<String> {
+        self.vars.get(key).filter(|v| !v.trim().is_empty()).cloned()
+    }
+
+    fn lookup(&self, path: &str) -> Option<toml::Value> {
+        let mut segments = path.split('.');
+        let mut value = self.file.get(segments.next()?)?;
+        for segment in segments {
+            value = value.as_table()?.get(segment)?;
+        }
+        Some(value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file: &str, vars: &[(&str, &str)]) -> Result<Config> {
+        let mut loader = Loader {
+            file: file.parse().unwrap(),
+            vars: vars
+                .iter()
+                .map(|(key, value)| ((*key).to_string(), (*value).to_string()))
+                .collect(),
+            errors: Vec::new(),
+        };
+        let config = loader.config();
+        loader.finish(None, config)
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = r#"
+            [persona]
+            dir = "from_file"
+            default = "alice"
+
+            [memory]
+            type = "in_memory"
+            in_memory.capacity = 50
+        "#;
+        let config = load(
+            file,
+            &[
+                ("PERSONA_DIR", "from_env"),
+                ("DEFAULT_PERSONA", "  "),
+                ("MEMORY_CAPACITY", "80"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.persona.dir, "from_env");
+        // A blank variable counts as unset
+        assert_eq!(config.persona.default, "alice");
+        assert!(matches!(
+            config.memory,
+            MemoryConfig::InMemory { capacity: 80, .. }
+        ));
+    }
+
+    #[test]
+    fn reports_every_error_at_once() {
+        let file = r#"
+            [memory]
+            type = "in_memory"
+            in_memory.capacity = 0
+
+            [tools]
+            enabled = ["no_such_tool"]
+        "#;
+        let err = load(file, &[("PLATFORM", "fax"), ("SUMMARY_KEEP_RECENT", "ten")])
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("`memory.in_memory.capacity` (MEMORY_CAPACITY) must be at least 1"));
+        assert!(err.contains("unknown tool 'no_such_tool'"));
+        assert!(err.contains("unknown platform 'fax'"));
+        assert!(err.contains("(SUMMARY_KEEP_RECENT) must be a non-negative integer, got 'ten'"));
+    }
+
+    #[test]
+    fn history_limit_must_exceed_threshold_only_with_memory() {
+        let vars = [("HISTORY_LIMIT", "20"), ("SUMMARY_THRESHOLD", "40")];
+
+        let err = load("", &[("MEMORY_TYPE", "in_memory"), vars[0], vars[1]]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("must be greater than `summary.threshold` (40)")
+        );
+
+        let config = load("", &[("MEMORY_TYPE", "none"), vars[0], vars[1]]).unwrap();
+        assert!(matches!(config.memory, MemoryConfig::None));
+        assert_eq!(config.history_limit, 20);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::prompt::Message;
+
+/// Fixed per-message cost covering role markers and separators.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Newest history messages (besides the current one) that take precedence
+/// over the example exchanges.
+const RECENT_HISTORY_MESSAGES: usize = 8;
+
+/// Token limits of a model's context window.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBudget {
+    /// Total tokens the model accepts (prompt + completion).
+    pub context_window: usize,
+    /// Tokens kept free for the model's reply.
+    pub reserved_output: usize,
+}
+
+impl Default for TokenBudget {
+    fn default() -> Self {
+        Self::new(8192, 1024)
+    }
+}
+
+impl TokenBudget {
+    #[must_use]
+    pub const fn new(context_window: usize, reserved_output: usize) -> Self {
+        Self {
+            context_window,
+            reserved_output,
+        }
+    }
+
+    /// Tokens available for the prompt.
+    #[must_use]
+    pub fn prompt_tokens(&self) -> usize {
+        self.context_window.saturating_sub(self.reserved_output)
+    }
+}
+
+/// Rough token estimate that needs no tokenizer: CJK characters are counted as
+/// one token each, everything else as four characters per token.
+#[must_use]
+pub fn estimate_tokens(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(cjk, other), c| {
+        if is_cjk(c) {
+            (cjk + 1, other)
+        } else {
+            (cjk, other + 1)
+        }
+    });
+    cjk + other.div_ceil(4)
+}
+
+#[must_use]
+pub fn estimate_message_tokens(message: &Message) -> usize {
+    MESSAGE_OVERHEAD_TOKENS + estimate_tokens(&message.content)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303F}'   // CJK punctuation
+        | '\u{3040}'..='\u{30FF}' // Hiragana / Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}' // Hangul
+        | '\u{FF00}'..='\u{FFEF}' // Full-width forms
+    )
+}
+
+/// Assembles the messages sent to the LLM so that they fit a [`TokenBudget`].
+///
+/// Pinned messages (the system prompt) are always kept, and so is the newest
+/// history message, truncated if it alone is too large. The few turns before
+/// it come next, then the example exchanges, dropped from the last one when
+/// they do not fit; the rest of the history is added from the newest message
+/// backwards and the oldest turns are dropped once the budget is exhausted.
+pub struct ContextBuilder {
+    budget: TokenBudget,
+    pinned: Vec<Message>,
+    examples: Vec<(Message, Message)>,
+    history: Vec<Message>,
+}
+
+impl ContextBuilder {
+    #[must_use]
+    pub fn new(budget: TokenBudget) -> Self {
+        Self {
+            budget,
+            pinned: Vec::new(),
+            examples: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Adds a message that is never dropped.
+    pub fn pin(&mut self, message: Message) -> &mut Self {
+        self.pinned.push(message);
+        self
+    }
+
+    /// Adds few-shot user/assistant exchanges, sent between the pinned messages
+    /// and the history.
+    pub fn examples(
+        &mut self,
+        exchanges: impl IntoIterator<Item = (Message, Message)>,
+    ) -> &mut Self {
+        self.examples.extend(exchanges);
+        self
+    }
+
+    /// Appends conversation history, oldest first.
+    pub fn history(&mut self, messages: impl IntoIterator<Item = Message>) -> &mut Self {
+        self.history.extend(messages);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> Vec<Message> {
+        let pinned_tokens: usize = self.pinned.iter().map(estimate_message_tokens).sum();
+        let mut remaining = self.budget.prompt_tokens().saturating_sub(pinned_tokens);
+        let mut history = self.history;
+
+        // Always send the current turn, even if it has to be cut short.
+        let current = history.pop().map(|message| {
+            let cost = estimate_message_tokens(&message);
+            if cost <= remaining {
+                remaining -= cost;
+                message
+            } else {
+                let truncated = truncate_message(message, remaining);
+                remaining = 0;
+                truncated
+            }
+        });
+
+        // Newest first; recent turns matter more than examples of the tone.
+        let total = history.len();
+        let mut older = history.into_iter().rev();
+        let mut kept = Vec::new();
+        let mut full = false;
+        for message in older.by_ref().take(RECENT_HISTORY_MESSAGES) {
+            let cost = estimate_message_tokens(&message);
+            if cost > remaining {
+                full = true;
+                break;
+            }
+            remaining -= cost;
+            kept.push(message);
+        }
+
+        let example_total = self.examples.len();
+        let mut examples = Vec::new();
+        for (user, assistant) in self.examples {
+            let cost = estimate_message_tokens(&user) + estimate_message_tokens(&assistant);
+            if cost > remaining {
+                break;
+            }
+            remaining -= cost;
+            examples.push(user);
+            examples.push(assistant);
+        }
+
+        if !full {
+            for message in older {
+                let cost = estimate_message_tokens(&message);
+                if cost > remaining {
+                    break;
+                }
+                remaining -= cost;
+                kept.push(message);
+            }
+        }
+
+        if kept.len() < total || examples.len() < example_total * 2 {
+            tracing::debug!(
+                "Context budget of {} tokens exceeded, dropped {} oldest messages and {} examples",
+                self.budget.prompt_tokens(),
+                total - kept.len(),
+                example_total - examples.len() / 2
+            );
+        }
+
+        kept.reverse();
+        kept.extend(current);
+
+        // A tool result is meaningless without the assistant turn that requested it.
+        let start = kept
+            .iter()
+            .position(|m| m.role != "tool")
+            .unwrap_or(kept.len());
+
+        let mut messages = self.pinned;
+        messages.extend(examples);
+        messages.extend(kept.into_iter().skip(start));
+        messages
+    }
+}
+
+fn truncate_message(mut message: Message, max_tokens: usize) -> Message {
+    let end = truncate_to_tokens(
+        &message.content,
+        max_tokens.saturating_sub(MESSAGE_OVERHEAD_TOKENS),
+    )
+    .len();
+    message.content.truncate(end);
+    message
+}
+
+/// Longest prefix of `text` estimated at no more than `max_tokens` tokens.
+#[must_use]
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> &str {
+    let mut budget = max_tokens;
+    let mut end = 0;
+    let mut ascii_run = 0;
+    for (idx, c) in text.char_indices() {
+        let cost = if is_cjk(c) {
+            1
+        } else {
+            // Every fourth non-CJK character starts a new token.
+            ascii_run += 1;
+            usize::from(ascii_run % 4 == 1)
+        };
+        if cost > budget {
+            break;
+        }
+        budget -= cost;
+        end = idx + c.len_utf8();
+    }
+    &text[..end]
+}
+
+/// Keeps the leading system messages and the newer half of the rest, for a
+/// retry after the provider rejected the prompt as too long.
+#[must_use]
+pub fn drop_oldest_half(messages: &[Message]) -> Vec<Message> {
+    let pinned = messages.iter().take_while(|m| m.role == "system").count();
+    let rest = &messages[pinned..];
+    let dropped = rest.len() / 2;
+
+    let mut trimmed = messages[..pinned].to_vec();
+    trimmed.extend(
+        rest[dropped..]
+            .iter()
+            .skip_while(|m| m.role == "tool")
+            .cloned(),
+    );
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message costing exactly `tokens` tokens.
+    fn sized(role: &str, tokens: usize) -> Message {
+        let mut message = Message::system(&"x".repeat((tokens - MESSAGE_OVERHEAD_TOKENS) * 4));
+        message.role = role.to_string();
+        message
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    fn turns(count: usize) -> Vec<Message> {
+        (0..count)
+            .map(|i| {
+                let role = if i % 2 == 0 { "user" } else { "assistant" };
+                let mut message = sized(role, 10);
+                message.content = format!("{i:0>24}");
+                message
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_everything_within_budget() {
+        let mut builder = ContextBuilder::new(TokenBudget::new(1000, 0));
+        builder
+            .pin(Message::system("prompt"))
+            .examples([(Message::user("ex", None), Message::assistant("ok"))])
+            .history(turns(3));
+        let messages = builder.build();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0].content, "prompt");
+        assert_eq!(messages[1].content, "ex");
+        assert_eq!(messages[5].role, "user");
+    }
+
+    #[test]
+    fn drops_oldest_history_first() {
+        // Pinned 10 + 5 turns of 10, with room for 3 turns
+        let mut builder = ContextBuilder::new(TokenBudget::new(40, 0));
+        builder.pin(sized("system", 10)).history(turns(5));
+        let messages = builder.build();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(contents(&messages[1..]), contents(&turns(5)[2..]));
+    }
+
+    #[test]
+    fn recent_history_outranks_examples() {
+        let examples = || (0..3).map(|_| (sized("user", 10), sized("assistant", 10)));
+        // Room for the 9 newest turns (current + 8 recent) and one example
+        let mut builder = ContextBuilder::new(TokenBudget::new(110, 0));
+        builder.examples(examples()).history(turns(12));
+        let messages = builder.build();
+        let history = &messages[2..];
+        assert_eq!(contents(history), contents(&turns(12)[3..]));
+
+        // With less room, examples go before any recent turn
+        let mut builder = ContextBuilder::new(TokenBudget::new(90, 0));
+        builder.examples(examples()).history(turns(12));
+        assert_eq!(contents(&builder.build()), contents(&turns(12)[3..]));
+    }
+
+    #[test]
+    fn truncates_an_oversized_current_message() {
+        let mut builder = ContextBuilder::new(TokenBudget::new(20, 0));
+        builder.history([sized("user", 100)]);
+        let messages = builder.build();
+        assert_eq!(messages.len(), 1);
+        assert!(estimate_message_tokens(&messages[0]) <= 20);
+    }
+
+    #[test]
+    fn drops_tool_results_without_their_request() {
+        let mut history = turns(1);
+        history.push(sized("tool", 10));
+        history.push(sized("user", 10));
+        let mut builder = ContextBuilder::new(TokenBudget::new(20, 0));
+        builder.history(history);
+        let messages = builder.build();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, "user");
+    }
+}
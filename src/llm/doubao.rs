@@ -1,104 +1,83 @@
-use std::env;
-
-use anyhow::{Context, Result};
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
 
-use crate::{llm::LLMClient, prompt::Message};
+use crate::{
+    config::DoubaoConfig,
+    context::TokenBudget,
+    llm::{
+        GenerationOptions, LLMClient, TokenStream, http::HttpClient,
+        openai_compat::OpenAICompatClient,
+    },
+    prompt::Message,
+    tools::ToolDefinition,
+};
+
+// Doubao API (Ark) is OpenAI compatible for chat completions
+pub const DEFAULT_BASE_URL: &str = "https://ark.cn-beijing.volces.com/api/v3";
+pub const DEFAULT_BUDGET: TokenBudget = TokenBudget::new(32_768, 4096);
 
 pub struct DoubaoClient {
-    api_key: String,
-    model_endpoint: String,
+    chat: OpenAICompatClient,
     vision_endpoint: Option<String>,
     #[allow(dead_code)]
     asr_endpoint: Option<String>,
     #[allow(dead_code)]
     tts_endpoint: Option<String>,
-    client: reqwest::Client,
 }
 
 impl DoubaoClient {
-    pub fn new() -> Result<Self> {
-        let api_key = env::var("DOUBAO_API_KEY").context("DOUBAO_API_KEY not set")?;
-        let model_endpoint = env::var("DOUBAO_MODEL")
-            .context("DOUBAO_MODEL not set (this should be the endpoint ID)")?;
-
-        let vision_endpoint = env::var("DOUBAO_VISION_MODEL").ok();
-        let asr_endpoint = env::var("DOUBAO_ASR_MODEL").ok();
-        let tts_endpoint = env::var("DOUBAO_TTS_MODEL").ok();
-
-        Ok(Self {
-            api_key,
-            model_endpoint,
-            vision_endpoint,
-            asr_endpoint,
-            tts_endpoint,
-            client: reqwest::Client::new(),
-        })
+    pub fn new(config: &DoubaoConfig, http: HttpClient) -> Self {
+        Self {
+            chat: OpenAICompatClient::from_config("Doubao/Ark", &config.chat, http),
+            vision_endpoint: config.vision_model.clone(),
+            asr_endpoint: config.asr_model.clone(),
+            tts_endpoint: config.tts_model.clone(),
+        }
     }
 }
 
-// Doubao API (Ark) is OpenAI compatible for chat completions
-#[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<Message>,
-}
-
-#[derive(Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
-}
-
-#[derive(Deserialize)]
-struct Choice {
-    message: Message,
-}
-
 #[async_trait]
 impl LLMClient for DoubaoClient {
-    async fn chat(&self, messages: &[Message]) -> Result<String> {
-        let request = ChatRequest {
-            model: self.model_endpoint.clone(),
-            messages: messages.to_vec(),
-        };
-
-        // Ark/Volcengine endpoint
-        let response = self
-            .client
-            .post("https://ark.cn-beijing.volces.com/api/v3/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Doubao/Ark API error: {error_text}");
-        }
+    async fn chat(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<String, LlmError> {
+        self.chat.chat(messages, options).await
+    }
 
-        let chat_response: ChatResponse = response.json().await?;
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream, LlmError> {
+        self.chat.chat_stream(messages, options).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<Message, LlmError> {
+        self.chat.chat_with_tools(messages, tools, options).await
+    }
 
-        chat_response
-            .choices
-            .first()
-            .map(|c| c.message.content.clone())
-            .context("No response choice from Doubao API")
+    fn token_budget(&self) -> TokenBudget {
+        self.chat.token_budget()
     }
 }
 
 use serde_json::json;
 
-use crate::llm::{VisionClient, VoiceClient};
+use crate::llm::{LlmError, VisionClient, VoiceClient};
 
 #[async_trait]
 impl VisionClient for DoubaoClient {
-    async fn analyze_image(&self, image_url: &str, prompt: &str) -> Result<String> {
+    async fn analyze_image(&self, image_url: &str, prompt: &str) -> Result<String, LlmError> {
         let endpoint = self
             .vision_endpoint
             .as_ref()
-            .context("Vision endpoint not configured")?;
+            .ok_or_else(|| LlmError::Unsupported("Vision endpoint not configured".to_string()))?;
 
         // Construct request for Vision Model (similar to Chat but with image_url)
         let messages = vec![json!({
@@ -114,18 +93,7 @@ impl VisionClient for DoubaoClient {
             "messages": messages
         });
 
-        let response = self
-            .client
-            .post("https://ark.cn-beijing.volces.com/api/v3/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Doubao Vision API error: {error_text}");
-        }
+        let response = self.chat.post_chat_completion(&request, false).await?;
 
         let body: serde_json::Value = response.json().await?;
         let content = body["choices"][0]["message"]["content"]
@@ -135,21 +103,27 @@ impl VisionClient for DoubaoClient {
         Ok(content)
     }
 
-    async fn analyze_video(&self, _video_url: &str, _prompt: &str) -> Result<String> {
+    async fn analyze_video(&self, _video_url: &str, _prompt: &str) -> Result<String, LlmError> {
         // Placeholder
-        anyhow::bail!("Video analysis not yet implemented")
+        Err(LlmError::Unsupported(
+            "Video analysis not yet implemented".to_string(),
+        ))
     }
 }
 
 #[async_trait]
 impl VoiceClient for DoubaoClient {
-    async fn speech_to_text(&self, _audio_data: &[u8]) -> Result<String> {
+    async fn speech_to_text(&self, _audio_data: &[u8]) -> Result<String, LlmError> {
         // Placeholder
-        anyhow::bail!("ASR not fully implemented yet")
+        Err(LlmError::Unsupported(
+            "ASR not fully implemented yet".to_string(),
+        ))
     }
 
-    async fn text_to_speech(&self, _text: &str) -> Result<Vec<u8>> {
+    async fn text_to_speech(&self, _text: &str) -> Result<Vec<u8>, LlmError> {
         // Placeholder
-        anyhow::bail!("TTS not fully implemented yet")
+        Err(LlmError::Unsupported(
+            "TTS not fully implemented yet".to_string(),
+        ))
     }
 }
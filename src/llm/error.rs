@@ -0,0 +1,319 @@
+use std::{fmt, time::Duration};
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// Failure of a provider call, classified so callers can react to it.
+#[derive(Debug)]
+pub enum LlmError {
+    /// 429 or an equivalent quota error.
+    RateLimited {
+        provider: String,
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// Missing, invalid or unauthorized API key.
+    Auth {
+        provider: String,
+        message: String,
+    },
+    /// The prompt or the reply was refused by the provider's content filter.
+    ContentPolicy {
+        provider: String,
+        message: String,
+    },
+    /// The prompt exceeds the model's context window.
+    ContextLength {
+        provider: String,
+        message: String,
+    },
+    /// Any other rejected request (4xx).
+    InvalidRequest {
+        provider: String,
+        status: u16,
+        message: String,
+    },
+    /// The provider failed on its side (5xx).
+    Server {
+        provider: String,
+        status: u16,
+        message: String,
+    },
+    Timeout,
+    Network(String),
+    /// A response that could not be understood.
+    InvalidResponse(String),
+    /// The capability is not configured or not implemented.
+    Unsupported(String),
+}
+
+// OpenAI/DeepSeek/xAI/Ark all use `{"error": {"message", "type", "code"}}`
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+}
+
+impl LlmError {
+    /// Classifies a non-2xx response from `provider`.
+    pub fn from_response(
+        provider: &str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> Self {
+        let (message, tags) = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(parsed) => {
+                let code = parsed.error.code.map(|c| match c {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                });
+                let tags = [parsed.error.kind, code]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (parsed.error.message, tags)
+            }
+            Err(_) => (body.trim().to_string(), String::new()),
+        };
+
+        let haystack = format!("{tags} {message}").to_lowercase();
+        let provider = provider.to_string();
+
+        if haystack.contains("context_length")
+            || haystack.contains("context length")
+            || haystack.contains("maximum context")
+            || haystack.contains("too many tokens")
+        {
+            Self::ContextLength { provider, message }
+        } else if haystack.contains("content_filter")
+            || haystack.contains("content_policy")
+            || haystack.contains("sensitivecontent")
+            || haystack.contains("safety")
+        {
+            Self::ContentPolicy { provider, message }
+        } else if status == StatusCode::TOO_MANY_REQUESTS || haystack.contains("rate_limit") {
+            Self::RateLimited {
+                provider,
+                retry_after,
+                message,
+            }
+        } else if status == StatusCode::UNAUTHORIZED
+            || status == StatusCode::FORBIDDEN
+            || haystack.contains("invalid_api_key")
+            || haystack.contains("authentication")
+        {
+            Self::Auth { provider, message }
+        } else if status.is_server_error() {
+            Self::Server {
+                provider,
+                status: status.as_u16(),
+                message,
+            }
+        } else {
+            Self::InvalidRequest {
+                provider,
+                status: status.as_u16(),
+                message,
+            }
+        }
+    }
+
+    /// Whether the failure says something about the provider's health (as
+    /// opposed to the request), i.e. whether another provider might succeed
+    /// and this one should count towards its circuit breaker.
+    #[must_use]
+    pub fn is_provider_fault(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. }
+                | Self::Auth { .. }
+                | Self::Server { .. }
+                | Self::Timeout
+                | Self::Network(_)
+                | Self::InvalidResponse(_)
+        )
+    }
+
+    /// Short text suitable for showing to the chat user.
+    #[must_use]
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            Self::RateLimited { .. } => {
+                "I'm receiving too many messages right now, please try again in a moment."
+            }
+            Self::Auth { .. } => {
+                "The AI service is not configured correctly, please contact the bot administrator."
+            }
+            Self::ContentPolicy { .. } => "Sorry, I can't respond to that.",
+            Self::ContextLength { .. } => {
+                "This conversation has grown too long for me, please start a new one."
+            }
+            Self::Server { .. } | Self::Timeout | Self::Network(_) => {
+                "The AI service is temporarily unavailable, please try again later."
+            }
+            Self::InvalidRequest { .. } | Self::InvalidResponse(_) => {
+                "Something went wrong while generating a reply."
+            }
+            Self::Unsupported(_) => "That feature is not available.",
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateLimited {
+                provider,
+                retry_after: Some(delay),
+                message,
+            } => write!(
+                f,
+                "{provider} rate limited (retry after {delay:?}): {message}"
+            ),
+            Self::RateLimited {
+                provider, message, ..
+            } => write!(f, "{provider} rate limited: {message}"),
+            Self::Auth { provider, message } => {
+                write!(f, "{provider} authentication failed: {message}")
+            }
+            Self::ContentPolicy { provider, message } => {
+                write!(f, "{provider} refused by content policy: {message}")
+            }
+            Self::ContextLength { provider, message } => {
+                write!(f, "{provider} context length exceeded: {message}")
+            }
+            Self::InvalidRequest {
+                provider,
+                status,
+                message,
+            } => write!(f, "{provider} API error ({status}): {message}"),
+            Self::Server {
+                provider,
+                status,
+                message,
+            } => write!(f, "{provider} server error ({status}): {message}"),
+            Self::Timeout => write!(f, "LLM request timed out"),
+            Self::Network(e) => write!(f, "Network error: {e}"),
+            Self::InvalidResponse(e) => write!(f, "Invalid LLM response: {e}"),
+            Self::Unsupported(what) => write!(f, "{what}"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout
+        } else if e.is_decode() {
+            Self::InvalidResponse(e.to_string())
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for LlmError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::InvalidResponse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn classify(status: u16, body: &str) -> LlmError {
+        LlmError::from_response("test", StatusCode::from_u16(status).unwrap(), None, body)
+    }
+
+    fn error_body(message: &str, kind: &str, code: &serde_json::Value) -> String {
+        json!({"error": {"message": message, "type": kind, "code": code}}).to_string()
+    }
+
+    #[test]
+    fn classifies_by_status() {
+        assert!(matches!(
+            classify(429, "slow down"),
+            LlmError::RateLimited { .. }
+        ));
+        assert!(matches!(classify(401, ""), LlmError::Auth { .. }));
+        assert!(matches!(classify(403, ""), LlmError::Auth { .. }));
+        assert!(matches!(
+            classify(503, "<html>Service Unavailable</html>"),
+            LlmError::Server { status: 503, .. }
+        ));
+        assert!(matches!(
+            classify(404, "not found"),
+            LlmError::InvalidRequest { status: 404, .. }
+        ));
+    }
+
+    #[test]
+    fn classifies_by_error_body() {
+        let body = error_body(
+            "This model's maximum context length is 8192 tokens",
+            "invalid_request_error",
+            &json!("context_length_exceeded"),
+        );
+        assert!(matches!(
+            classify(400, &body),
+            LlmError::ContextLength { .. }
+        ));
+
+        let body = error_body("Blocked", "", &json!("content_filter"));
+        assert!(matches!(
+            classify(400, &body),
+            LlmError::ContentPolicy { .. }
+        ));
+
+        // Quota errors that do not use 429
+        let body = error_body("Too fast", "rate_limit_exceeded", &serde_json::Value::Null);
+        assert!(matches!(classify(400, &body), LlmError::RateLimited { .. }));
+
+        let body = error_body("Incorrect API key", "", &json!("invalid_api_key"));
+        assert!(matches!(classify(400, &body), LlmError::Auth { .. }));
+    }
+
+    #[test]
+    fn content_errors_win_over_status() {
+        let body = error_body("Input too long", "", &json!("context_length_exceeded"));
+        assert!(matches!(
+            classify(429, &body),
+            LlmError::ContextLength { .. }
+        ));
+    }
+
+    #[test]
+    fn keeps_message_and_retry_after() {
+        let error = LlmError::from_response(
+            "deepseek",
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(3)),
+            &error_body("Slow down", "", &json!(42)),
+        );
+        assert!(matches!(
+            &error,
+            LlmError::RateLimited { retry_after: Some(delay), message, .. }
+                if *delay == Duration::from_secs(3) && message == "Slow down"
+        ));
+        assert_eq!(
+            classify(500, "  plain text  ").to_string(),
+            "test server error (500): plain text"
+        );
+    }
+}
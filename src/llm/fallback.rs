@@ -0,0 +1,268 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::{FutureExt, StreamExt};
+
+use crate::{
+    context::TokenBudget,
+    llm::{GenerationOptions, LLMClient, LlmError, TokenStream},
+    prompt::Message,
+    tools::ToolDefinition,
+};
+
+/// When a provider is taken out of rotation.
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerPolicy {
+    /// Consecutive failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit skips the provider before it is tried again.
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown: Duration::from_mins(1),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+struct Provider {
+    name: String,
+    client: Arc<dyn LLMClient>,
+    state: Mutex<BreakerState>,
+}
+
+impl Provider {
+    /// Closed, or open with the cooldown elapsed (a trial request is allowed).
+    fn is_available(&self) -> bool {
+        let state = self.state.lock().expect("breaker lock poisoned");
+        state.open_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().expect("breaker lock poisoned") = BreakerState::default();
+    }
+
+    fn record_failure(&self, policy: &BreakerPolicy, error: &LlmError) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        state.consecutive_failures += 1;
+
+        if state.consecutive_failures >= policy.failure_threshold {
+            state.open_until = Some(Instant::now() + policy.cooldown);
+            tracing::warn!(
+                "Provider '{}' failed {} times in a row, skipping it for {:?}: {}",
+                self.name,
+                state.consecutive_failures,
+                policy.cooldown,
+                error
+            );
+        } else {
+            tracing::warn!("Provider '{}' failed, trying next: {}", self.name, error);
+        }
+    }
+}
+
+/// Tries an ordered list of providers and falls through to the next one on
+/// errors (including timeouts). Providers that keep failing are skipped for a
+/// cooldown period.
+pub struct FallbackClient {
+    providers: Vec<Provider>,
+    policy: BreakerPolicy,
+}
+
+impl FallbackClient {
+    pub fn new(providers: Vec<(String, Arc<dyn LLMClient>)>, policy: BreakerPolicy) -> Self {
+        Self {
+            providers: providers
+                .into_iter()
+                .map(|(name, client)| Provider {
+                    name,
+                    client,
+                    state: Mutex::new(BreakerState::default()),
+                })
+                .collect(),
+            policy,
+        }
+    }
+
+    /// Providers to try, in order. If every circuit is open, all are tried
+    /// rather than failing without a request.
+    fn candidates(&self) -> Vec<&Provider> {
+        let available: Vec<&Provider> =
+            self.providers.iter().filter(|p| p.is_available()).collect();
+        if available.is_empty() {
+            self.providers.iter().collect()
+        } else {
+            available
+        }
+    }
+
+    async fn first_success<'a, T, F>(&'a self, call: F) -> Result<T, LlmError>
+    where
+        F: Fn(&'a Provider) -> futures::future::BoxFuture<'a, Result<T, LlmError>>,
+    {
+        let mut last_error = None;
+        for provider in self.candidates() {
+            match call(provider).await {
+                Ok(value) => {
+                    provider.record_success();
+                    return Ok(value);
+                }
+                // Errors about the request itself (e.g. a content-policy refusal)
+                // do not make the provider unhealthy, but another one may still
+                // accept the request.
+                Err(e) if !e.is_provider_fault() => {
+                    tracing::warn!("Provider '{}' rejected the request: {}", provider.name, e);
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    provider.record_failure(&self.policy, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| LlmError::Unsupported("No LLM providers configured".to_string())))
+    }
+}
+
+#[async_trait]
+impl LLMClient for FallbackClient {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<String, LlmError> {
+        self.first_success(|p| p.client.chat(messages, options))
+            .await
+    }
+
+    /// Falls through only while no delta has been delivered: a provider whose
+    /// stream fails before its first delta counts as failed. A stream that
+    /// breaks midway is reported as an error.
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream, LlmError> {
+        self.first_success(|p| {
+            async move {
+                let mut stream = p.client.chat_stream(messages, options).await?;
+                match stream.next().await {
+                    Some(Ok(first)) => Ok(futures::stream::once(async move { Ok(first) })
+                        .chain(stream)
+                        .boxed()),
+                    Some(Err(e)) => Err(e),
+                    None => Ok(futures::stream::empty().boxed()),
+                }
+            }
+            .boxed()
+        })
+        .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<Message, LlmError> {
+        self.first_success(|p| p.client.chat_with_tools(messages, tools, options))
+            .await
+    }
+
+    /// The smallest budget of the chain, so any provider can take the prompt.
+    fn token_budget(&self) -> TokenBudget {
+        self.providers
+            .iter()
+            .map(|p| p.client.token_budget())
+            .min_by_key(TokenBudget::prompt_tokens)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Streams `deltas`, failing where a delta is `None`.
+    struct Scripted(Vec<Option<&'static str>>);
+
+    #[async_trait]
+    impl LLMClient for Scripted {
+        async fn chat(&self, _: &[Message], _: &GenerationOptions) -> Result<String, LlmError> {
+            unreachable!("only streaming is exercised")
+        }
+
+        async fn chat_stream(
+            &self,
+            _: &[Message],
+            _: &GenerationOptions,
+        ) -> Result<TokenStream, LlmError> {
+            let deltas: Vec<Result<String, LlmError>> = self
+                .0
+                .iter()
+                .map(|delta| {
+                    delta.map(str::to_string).ok_or_else(|| LlmError::Server {
+                        provider: "scripted".to_string(),
+                        status: 500,
+                        message: "broken stream".to_string(),
+                    })
+                })
+                .collect();
+            Ok(futures::stream::iter(deltas).boxed())
+        }
+    }
+
+    fn chain(first: Vec<Option<&'static str>>) -> FallbackClient {
+        FallbackClient::new(
+            vec![
+                ("first".to_string(), Arc::new(Scripted(first))),
+                ("second".to_string(), Arc::new(Scripted(vec![Some("ok")]))),
+            ],
+            BreakerPolicy {
+                failure_threshold: 1,
+                cooldown: Duration::from_mins(1),
+            },
+        )
+    }
+
+    async fn collect(client: &FallbackClient) -> Vec<Result<String, LlmError>> {
+        let stream = client
+            .chat_stream(&[], &GenerationOptions::default())
+            .await
+            .unwrap();
+        stream.collect().await
+    }
+
+    #[tokio::test]
+    async fn stream_failing_before_first_delta_falls_through() {
+        let client = chain(vec![None]);
+        let deltas = collect(&client).await;
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].as_ref().unwrap(), "ok");
+        // The failure counted towards the first provider's circuit
+        assert!(!client.providers[0].is_available());
+    }
+
+    #[tokio::test]
+    async fn stream_failing_midway_is_reported() {
+        let client = chain(vec![Some("partial"), None]);
+        let deltas = collect(&client).await;
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].as_ref().unwrap(), "partial");
+        assert!(deltas[1].is_err());
+        assert!(client.providers[0].is_available());
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::{RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+
+use crate::llm::LlmError;
+
+/// Timeouts and retry behaviour shared by every provider request.
+#[derive(Debug, Clone)]
+pub struct HttpPolicy {
+    pub connect_timeout: Duration,
+    /// Maximum wait for a complete response, or between two chunks of a stream.
+    pub read_timeout: Duration,
+    /// Total attempts per request, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    /// Upper bound for a single backoff; a longer `Retry-After` is not waited for.
+    pub max_delay: Duration,
+}
+
+impl Default for HttpPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_mins(2),
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(20),
+        }
+    }
+}
+
+impl HttpPolicy {
+    /// Exponential backoff with jitter: a random delay in `[d/2, d]` where
+    /// `d = base_delay * 2^(attempt - 1)`, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        exp.mul_f64(fastrand::f64().mul_add(0.5, 0.5))
+    }
+}
+
+/// Request layer for the `llm` module: applies the [`HttpPolicy`] timeouts and
+/// retries network errors, 429 and 5xx responses.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    policy: HttpPolicy,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new(HttpPolicy::default()).expect("Failed to build HTTP client")
+    }
+}
+
+impl HttpClient {
+    pub fn new(policy: HttpPolicy) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(policy.connect_timeout)
+            .build()?;
+        Ok(Self { client, policy })
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Maximum silence between two chunks of a streamed body.
+    pub fn read_timeout(&self) -> Duration {
+        self.policy.read_timeout
+    }
+
+    /// Sends a request whose whole body is read within the read timeout.
+    /// Returns the last response, successful or not, once retries are exhausted.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, LlmError> {
+        let request = request.timeout(self.policy.read_timeout);
+        self.send_with_retry(request).await
+    }
+
+    /// Sends a request whose body is streamed: only the wait for the response
+    /// headers is bounded here, chunk gaps are bounded by the reader.
+    pub async fn send_streaming(&self, request: RequestBuilder) -> Result<Response, LlmError> {
+        self.send_with_retry(request).await
+    }
+
+    async fn send_with_retry(&self, request: RequestBuilder) -> Result<Response, LlmError> {
+        let mut attempt = 1;
+        loop {
+            let attempt_request = request.try_clone().ok_or_else(|| {
+                LlmError::Unsupported("Request body cannot be retried".to_string())
+            })?;
+            let result =
+                tokio::time::timeout(self.policy.read_timeout, attempt_request.send()).await;
+
+            let retry_in = match result {
+                Ok(Ok(response)) if !is_retryable(response.status()) => return Ok(response),
+                Ok(Ok(response)) => {
+                    let delay =
+                        retry_after(&response).unwrap_or_else(|| self.policy.backoff(attempt));
+                    if attempt >= self.policy.max_attempts || delay > self.policy.max_delay {
+                        return Ok(response);
+                    }
+                    tracing::warn!(
+                        "LLM request returned {} (attempt {}/{}), retrying in {:?}",
+                        response.status(),
+                        attempt,
+                        self.policy.max_attempts,
+                        delay
+                    );
+                    delay
+                }
+                Ok(Err(e)) => {
+                    if attempt >= self.policy.max_attempts {
+                        return Err(e.into());
+                    }
+                    let delay = self.policy.backoff(attempt);
+                    tracing::warn!(
+                        "LLM request failed (attempt {}/{}): {}, retrying in {:?}",
+                        attempt,
+                        self.policy.max_attempts,
+                        e,
+                        delay
+                    );
+                    delay
+                }
+                Err(_) => {
+                    if attempt >= self.policy.max_attempts {
+                        return Err(LlmError::Timeout);
+                    }
+                    let delay = self.policy.backoff(attempt);
+                    tracing::warn!(
+                        "LLM request timed out (attempt {}/{}), retrying in {:?}",
+                        attempt,
+                        self.policy.max_attempts,
+                        delay
+                    );
+                    delay
+                }
+            };
+
+            tokio::time::sleep(retry_in).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Parses `Retry-After` as delay-seconds or an HTTP date.
+pub(crate) fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
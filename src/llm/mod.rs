@@ -1,31 +1,80 @@
-use anyhow::Result;
 use async_trait::async_trait;
+use futures::{StreamExt, stream::BoxStream};
 
-use crate::prompt::Message;
+pub use self::{error::LlmError, options::GenerationOptions};
+use crate::{context::TokenBudget, prompt::Message, tools::ToolDefinition};
+
+/// Incremental text deltas of a reply, in arrival order.
+pub type TokenStream = BoxStream<'static, Result<String, LlmError>>;
 
 #[async_trait]
 pub trait LLMClient: Send + Sync {
-    async fn chat(&self, messages: &[Message]) -> Result<String>;
+    /// Generates a reply to `messages`, sampling with `options`.
+    async fn chat(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<String, LlmError>;
+
+    /// Streams the reply as it is generated. Clients without native streaming
+    /// yield the whole reply as a single delta.
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream, LlmError> {
+        let reply = self.chat(messages, options).await?;
+        Ok(futures::stream::once(async move { Ok(reply) }).boxed())
+    }
+
+    /// Sends `messages` with `tools` available and returns the assistant message,
+    /// which carries `tool_calls` when the model wants tools invoked. Clients
+    /// without tool support ignore `tools` and just answer.
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        _tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<Message, LlmError> {
+        Ok(Message::assistant(&self.chat(messages, options).await?))
+    }
+
+    /// Context limits of the underlying model, used to trim the prompt.
+    fn token_budget(&self) -> TokenBudget {
+        TokenBudget::default()
+    }
 }
 
 #[async_trait]
 pub trait VisionClient: Send + Sync {
-    async fn analyze_image(&self, image_url_or_base64: &str, prompt: &str) -> Result<String>;
-    async fn analyze_video(&self, video_url_or_data: &str, prompt: &str) -> Result<String>;
+    async fn analyze_image(
+        &self,
+        image_url_or_base64: &str,
+        prompt: &str,
+    ) -> Result<String, LlmError>;
+    async fn analyze_video(
+        &self,
+        video_url_or_data: &str,
+        prompt: &str,
+    ) -> Result<String, LlmError>;
 }
 
 #[async_trait]
 pub trait VoiceClient: Send + Sync {
-    async fn speech_to_text(&self, audio_data: &[u8]) -> Result<String>;
+    async fn speech_to_text(&self, audio_data: &[u8]) -> Result<String, LlmError>;
     #[allow(dead_code)]
-    async fn text_to_speech(&self, text: &str) -> Result<Vec<u8>>;
+    async fn text_to_speech(&self, text: &str) -> Result<Vec<u8>, LlmError>;
 }
 
 pub struct MockLLM;
 
 #[async_trait]
 impl LLMClient for MockLLM {
-    async fn chat(&self, messages: &[Message]) -> Result<String> {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        _options: &GenerationOptions,
+    ) -> Result<String, LlmError> {
         // Simple echo/dummy response for verification
         let last_msg = messages
             .last()
@@ -37,4 +86,11 @@ impl LLMClient for MockLLM {
 
 pub mod deepseek;
 pub mod doubao;
+pub mod error;
+pub mod fallback;
 pub mod grok;
+pub mod http;
+pub mod openai_compat;
+pub mod options;
+pub mod router;
+mod sse;
@@ -0,0 +1,217 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::EndpointConfig,
+    context::TokenBudget,
+    llm::{
+        GenerationOptions, LLMClient, LlmError, TokenStream,
+        http::{self, HttpClient},
+        sse,
+    },
+    prompt::Message,
+    tools::ToolDefinition,
+};
+
+/// Client for any endpoint speaking the `OpenAI` chat-completions protocol
+/// (Ollama, vLLM, LM Studio, `OpenRouter`, `DeepSeek`, Ark, xAI, ...).
+pub struct OpenAICompatClient {
+    name: String,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    extra_headers: Vec<(String, String)>,
+    budget: TokenBudget,
+    http: HttpClient,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolSpec<'a>>,
+    #[serde(flatten)]
+    options: &'a GenerationOptions,
+}
+
+#[derive(Serialize)]
+struct ToolSpec<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: &'a ToolDefinition,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+impl OpenAICompatClient {
+    /// `name` is only used in error messages; `base_url` is the API root that
+    /// `/chat/completions` is appended to (e.g. `http://localhost:11434/v1`).
+    pub fn new(name: &str, base_url: &str, api_key: Option<String>, model: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            extra_headers: Vec::new(),
+            budget: TokenBudget::default(),
+            http: HttpClient::default(),
+        }
+    }
+
+    /// Adds a header sent with every request (e.g. `HTTP-Referer` for `OpenRouter`).
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.extra_headers
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_budget(mut self, budget: TokenBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Replaces the default timeout and retry behaviour.
+    #[must_use]
+    pub fn with_http(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Builds a client for the endpoint described by `config`.
+    pub fn from_config(name: &str, config: &EndpointConfig, http: HttpClient) -> Self {
+        let mut client = Self::new(
+            name,
+            &config.base_url,
+            config.api_key.clone(),
+            &config.model,
+        )
+        .with_budget(config.budget)
+        .with_http(http);
+        for (name, value) in &config.extra_headers {
+            client = client.with_header(name, value);
+        }
+        client
+    }
+
+    /// POSTs `body` to the chat-completions endpoint and turns non-2xx replies
+    /// into a classified [`LlmError`]. Transient failures are retried according
+    /// to the client's `HttpPolicy`.
+    pub(crate) async fn post_chat_completion<T: Serialize + Sync>(
+        &self,
+        body: &T,
+        stream: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        let mut request = self
+            .http
+            .post(&format!("{}/chat/completions", self.base_url))
+            .json(body);
+
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        for (name, value) in &self.extra_headers {
+            request = request.header(name, value);
+        }
+
+        let response = if stream {
+            self.http.send_streaming(request).await?
+        } else {
+            self.http.send(request).await?
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = http::retry_after(&response);
+            let error_text = response.text().await?;
+            return Err(LlmError::from_response(
+                &self.name,
+                status,
+                retry_after,
+                &error_text,
+            ));
+        }
+
+        Ok(response)
+    }
+
+    async fn send(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+        stream: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        let request = ChatRequest {
+            model: options.model.as_deref().unwrap_or(&self.model),
+            messages,
+            stream,
+            tools: tools
+                .iter()
+                .map(|function| ToolSpec {
+                    kind: "function",
+                    function,
+                })
+                .collect(),
+            options,
+        };
+        self.post_chat_completion(&request, stream).await
+    }
+}
+
+#[async_trait]
+impl LLMClient for OpenAICompatClient {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<String, LlmError> {
+        Ok(self.chat_with_tools(messages, &[], options).await?.content)
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream, LlmError> {
+        let response = self.send(messages, &[], options, true).await?;
+        Ok(sse::chat_completion_deltas(
+            response,
+            self.http.read_timeout(),
+        ))
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<Message, LlmError> {
+        let response = self.send(messages, tools, options, false).await?;
+        let chat_response: ChatResponse = response.json().await?;
+
+        chat_response
+            .choices
+            .into_iter()
+            .next()
+            .map(|c| c.message)
+            .ok_or_else(|| {
+                LlmError::InvalidResponse(format!("No response choice from {} API", self.name))
+            })
+    }
+
+    fn token_budget(&self) -> TokenBudget {
+        self.budget
+    }
+}
@@ -0,0 +1,49 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::llm::LLMClient;
+
+/// The default client (a single provider or the fallback chain) plus every
+/// configured provider by name, so that personas can pick their own.
+pub struct ProviderRouter {
+    default: Arc<dyn LLMClient>,
+    providers: HashMap<String, Arc<dyn LLMClient>>,
+}
+
+impl ProviderRouter {
+    pub fn new(default: Arc<dyn LLMClient>) -> Self {
+        Self {
+            default,
+            providers: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_provider(mut self, name: &str, client: Arc<dyn LLMClient>) -> Self {
+        self.providers.insert(name.to_string(), client);
+        self
+    }
+
+    /// Whether `provider` names a configured provider rather than falling back
+    /// to the default client.
+    pub fn is_configured(&self, provider: &str) -> bool {
+        self.providers.contains_key(&provider.to_lowercase())
+    }
+
+    /// Client for `provider`, or the default one when it is `None` or not
+    /// configured.
+    pub fn route(&self, provider: Option<&str>) -> Arc<dyn LLMClient> {
+        let Some(name) = provider else {
+            return self.default.clone();
+        };
+        self.providers
+            .get(&name.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| {
+                tracing::warn!(
+                    "Provider '{}' is not configured, using the default client",
+                    name
+                );
+                self.default.clone()
+            })
+    }
+}
@@ -0,0 +1,218 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{StreamExt, stream::BoxStream};
+use serde::Deserialize;
+
+use crate::llm::{LlmError, TokenStream};
+
+// OpenAI-compatible streaming chunk: `data: {"choices":[{"delta":{"content":"..."}}]}`
+#[derive(Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    content: Option<String>,
+}
+
+struct SseState {
+    body: BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    pending: VecDeque<String>,
+    /// Error to report once the deltas queued before it are out.
+    failed: Option<LlmError>,
+    done: bool,
+}
+
+impl SseState {
+    /// Consumes every complete line in the buffer, queueing any content deltas.
+    fn drain_lines(&mut self) -> Result<(), LlmError> {
+        // Split on raw bytes so multi-byte UTF-8 characters are never cut in half.
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            self.handle_line(&String::from_utf8_lossy(&line))?;
+            if self.done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_line(&mut self, line: &str) -> Result<(), LlmError> {
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            // Comments, `event:` and blank separator lines carry no content.
+            return Ok(());
+        };
+
+        let data = data.trim();
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(());
+        }
+
+        let chunk: StreamChunk = serde_json::from_str(data)
+            .map_err(|e| LlmError::InvalidResponse(format!("Malformed stream chunk: {e}")))?;
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                self.pending.push_back(content);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns a streaming chat-completions response into a stream of content deltas.
+/// The stream fails if no data arrives for `idle_timeout`.
+pub fn chat_completion_deltas(response: reqwest::Response, idle_timeout: Duration) -> TokenStream {
+    let body = response
+        .bytes_stream()
+        .map(|r| r.map(|b| b.to_vec()))
+        .boxed();
+    deltas(body, idle_timeout)
+}
+
+fn deltas(
+    body: BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    idle_timeout: Duration,
+) -> TokenStream {
+    let state = SseState {
+        body,
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        failed: None,
+        done: false,
+    };
+
+    futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            if let Some(token) = state.pending.pop_front() {
+                return Some((Ok(token), state));
+            }
+            if let Some(e) = state.failed.take() {
+                return Some((Err(e), state));
+            }
+            if state.done {
+                return None;
+            }
+
+            let Ok(next) = tokio::time::timeout(idle_timeout, state.body.next()).await else {
+                state.done = true;
+                return Some((Err(LlmError::Timeout), state));
+            };
+
+            match next {
+                Some(Ok(bytes)) => {
+                    state.buffer.extend_from_slice(&bytes);
+                    if let Err(e) = state.drain_lines() {
+                        state.done = true;
+                        state.failed = Some(e);
+                    }
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    // Flush a trailing line that was not newline-terminated.
+                    state.done = true;
+                    let rest = String::from_utf8_lossy(&state.buffer).into_owned();
+                    state.buffer.clear();
+                    if let Err(e) = state.handle_line(&rest) {
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every item of the stream parsed from `chunks`, errors as their message.
+    async fn parse(chunks: &[&[u8]]) -> Vec<Result<String, String>> {
+        let chunks: Vec<reqwest::Result<Vec<u8>>> =
+            chunks.iter().map(|chunk| Ok(chunk.to_vec())).collect();
+        deltas(
+            futures::stream::iter(chunks).boxed(),
+            Duration::from_secs(5),
+        )
+        .map(|item| item.map_err(|e| e.to_string()))
+        .collect()
+        .await
+    }
+
+    fn delta(content: &str) -> String {
+        format!(
+            "data: {}\n\n",
+            serde_json::json!({"choices": [{"delta": {"content": content}}]})
+        )
+    }
+
+    #[tokio::test]
+    async fn yields_deltas_until_done() {
+        let body = format!(
+            ": keep-alive\nevent: message\n{}{}data: [DONE]\n\n{}",
+            delta("Hel"),
+            delta("lo"),
+            delta("ignored")
+        );
+        assert_eq!(
+            parse(&[body.as_bytes()]).await,
+            [Ok("Hel".to_string()), Ok("lo".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn joins_lines_split_across_chunks() {
+        // "你" is split between its UTF-8 bytes
+        let body = delta("你好");
+        let split = body.find('你').unwrap() + 1;
+        let (first, second) = body.as_bytes().split_at(split);
+        assert_eq!(parse(&[first, second]).await, [Ok("你好".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn flushes_unterminated_last_line() {
+        let body = delta("end");
+        assert_eq!(
+            parse(&[body.trim_end().as_bytes()]).await,
+            [Ok("end".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_empty_deltas_and_fails_on_malformed_chunks() {
+        let body = format!(
+            "{}data: {{\"choices\": [{{}}]}}\n{}data: {{oops\n",
+            delta(""),
+            delta("a")
+        );
+        let items = parse(&[body.as_bytes()]).await;
+        assert_eq!(items[0], Ok("a".to_string()));
+        assert!(
+            items[1]
+                .as_ref()
+                .unwrap_err()
+                .contains("Malformed stream chunk")
+        );
+        assert_eq!(items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn times_out_when_idle() {
+        let body = futures::stream::pending::<reqwest::Result<Vec<u8>>>().boxed();
+        let items: Vec<_> = deltas(body, Duration::from_millis(10)).collect().await;
+        assert!(matches!(items.as_slice(), [Err(LlmError::Timeout)]));
+    }
+}
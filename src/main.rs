@@ -1,17 +1,21 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dotenv::dotenv;
 
 mod bot;
+mod config;
+mod context;
 mod llm;
 mod memory;
 mod persona;
 mod platform;
 pub mod prompt;
+mod tools;
 
 use bot::Bot;
-use llm::MockLLM;
+use config::{Config, MemoryConfig, PersonaConfig, PlatformConfig, ProviderConfig};
+use llm::{MockLLM, http::HttpClient};
 use platform::{Platform, terminal::TerminalPlatform};
 
 #[tokio::main]
@@ -23,84 +27,454 @@ async fn main() -> Result<()> {
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
+    // 0. Load Configuration (--config <path>, CHATBOT_CONFIG or ./chatbot.toml)
+    let args = parse_args()?;
+    // Checking personas needs only their directory, not a complete config
+    if let Command::CheckPersonas = args.command {
+        return check_personas(args.config);
+    }
+    let config = Config::load(args.config)?;
+
+    match args.command {
+        Command::Run | Command::CheckPersonas => {}
+        Command::Migrate => return migrate(&config.memory).await,
+        Command::Sessions(command) => return sessions(&config.memory, command).await,
+    }
+
     tracing::info!("Initializing AI Chatbot...");
 
-    // 1. Initialize LLM Client
-    let provider = std::env::var("LLM_PROVIDER").unwrap_or_else(|_| "mock".to_string());
+    // 1. Initialize LLM Client(s)
+    // Several providers build a fallback chain tried in order.
+    let http = HttpClient::new(config.llm.http.clone())?;
 
-    let llm_client: Arc<dyn llm::LLMClient>;
+    let mut chain: Vec<(String, Arc<dyn llm::LLMClient>)> = Vec::new();
+    // Every provider by name, for personas that pick their own
+    let mut named: Vec<(String, Arc<dyn llm::LLMClient>)> =
+        vec![("mock".to_string(), Arc::new(MockLLM))];
     let mut vision_client: Option<Arc<dyn llm::VisionClient>> = None;
     let mut voice_client: Option<Arc<dyn llm::VoiceClient>> = None;
 
-    match provider.to_lowercase().as_str() {
-        "deepseek" => {
-            llm_client = Arc::new(
-                llm::deepseek::DeepSeekClient::new().expect("Failed to init DeepSeek client"),
+    for provider in &config.llm.providers {
+        let clients = build_provider(provider, &http);
+        // Multi-modal capabilities come from the first provider offering them
+        vision_client = vision_client.or(clients.vision);
+        voice_client = voice_client.or(clients.voice);
+        named.push((provider.name().to_string(), clients.llm.clone()));
+        chain.push((provider.name().to_string(), clients.llm));
+    }
+    for provider in &config.llm.extra {
+        named.push((
+            provider.name().to_string(),
+            build_provider(provider, &http).llm,
+        ));
+    }
+
+    let llm_client: Arc<dyn llm::LLMClient> = match chain.len() {
+        0 => Arc::new(MockLLM),
+        1 => chain.remove(0).1,
+        _ => {
+            tracing::info!(
+                "Using provider fallback chain: {}",
+                chain
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
             );
+            Arc::new(llm::fallback::FallbackClient::new(
+                chain,
+                config.llm.circuit,
+            ))
         }
-        "doubao" => {
-            let client =
-                Arc::new(llm::doubao::DoubaoClient::new().expect("Failed to init Doubao client"));
-            llm_client = client.clone();
-            vision_client = Some(client.clone() as Arc<dyn llm::VisionClient>);
-            voice_client = Some(client.clone() as Arc<dyn llm::VoiceClient>);
+    };
+
+    let router = named.into_iter().fold(
+        llm::router::ProviderRouter::new(llm_client.clone()),
+        |router, (name, client)| router.with_provider(&name, client),
+    );
+
+    // 2. Initialize Memory (Optional)
+    let memory = build_memory(&config.memory).await?;
+
+    // Summarize long sessions (only meaningful with a memory)
+    let summarizer = if memory.is_some() {
+        memory::summary::Summarizer::from_config(llm_client.clone(), config.summary).map(Arc::new)
+    } else {
+        None
+    };
+
+    // 3. Initialize Persona Manager
+    tracing::info!("Loading Personas...");
+    let persona_manager = std::sync::Arc::new(
+        persona::PersonaManager::new(&config.persona.dir, &config.persona.default)
+            .context("Failed to initialize Persona Manager")?,
+    );
+    // Pick up edits to persona files without a restart
+    let _persona_watcher = persona_manager
+        .watch()
+        .context("Failed to watch the avatars directory")?;
+
+    // Built-in tools the model may call
+    let tools = tools::ToolRegistry::from_names(&config.tools);
+
+    // 4. Initialize Bot Core
+    let bot = std::sync::Arc::new(
+        Bot::new(
+            router,
+            memory.clone(),
+            summarizer,
+            persona_manager,
+            tools,
+            vision_client,
+            voice_client,
+        )
+        .with_history_limit(config.history_limit),
+    );
+
+    // 5. Initialize Platform Adapter, until it stops, Ctrl-C or SIGTERM
+    let result = run_until_stopped(&config.platform, bot).await;
+
+    // Even when the platform failed, so the snapshot is not lost
+    if let Some(mem) = &memory
+        && let Err(e) = mem.shutdown().await
+    {
+        let e = e.context("Failed to shut down memory");
+        if result.is_ok() {
+            return Err(e);
         }
-        "grok" => {
-            llm_client =
-                Arc::new(llm::grok::GrokClient::new().expect("Failed to init Grok client"));
+        tracing::error!("{e:#}");
+    }
+
+    result
+}
+
+/// Runs the platform until it stops on its own or the process is asked to
+/// stop with Ctrl-C or (on Unix) SIGTERM.
+async fn run_until_stopped(config: &PlatformConfig, bot: Arc<Bot>) -> Result<()> {
+    tokio::select! {
+        result = run_platform(config, bot) => result,
+        () = shutdown_signal() => {
+            tracing::info!("Shutting down...");
+            Ok(())
         }
-        _ => {
-            if provider != "mock" {
-                tracing::warn!("Unknown provider '{}', falling back to MockLLM", provider);
+    }
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
             }
-            llm_client = Arc::new(MockLLM);
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to listen for SIGTERM, only Ctrl-C stops the bot: {}",
+                e
+            );
+            let _ = tokio::signal::ctrl_c().await;
         }
     }
+}
 
-    // 2. Initialize Memory (Optional)
-    let memory_type = std::env::var("MEMORY_TYPE").unwrap_or_else(|_| "none".to_string());
-    let memory: Option<std::sync::Arc<dyn memory::Memory>> = match memory_type.as_str() {
-        "postgres" => {
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+async fn run_platform(config: &PlatformConfig, bot: Arc<Bot>) -> Result<()> {
+    match config {
+        PlatformConfig::OneBot { ws_url } => {
+            let platform = platform::onebot::OneBotPlatform::new(ws_url);
+            platform.run(bot).await
+        }
+        PlatformConfig::Terminal => {
+            let platform = TerminalPlatform;
+            platform.run(bot).await
+        }
+    }
+}
+
+const USAGE: &str = "Usage: chatbot [--config <path>] [command]
+
+Commands:
+  personas check                      validate the persona files
+  migrate                             apply pending Postgres migrations
+  sessions [list]                     list the stored sessions
+  sessions show <session> [before]    show messages, newest page first
+  sessions delete <session> <id>      delete one message
+  sessions clear <session>            delete a session's history and metadata";
+
+/// Messages shown per `sessions show` page.
+const PAGE_SIZE: usize = 20;
+
+struct Args {
+    config: Option<PathBuf>,
+    command: Command,
+}
+
+enum Command {
+    /// Start the bot on the configured platform.
+    Run,
+    /// `personas check`: report every problem with the persona files.
+    CheckPersonas,
+    /// `migrate`: apply pending Postgres schema migrations.
+    Migrate,
+    /// `sessions ...`: browse and moderate the stored history.
+    Sessions(SessionsCommand),
+}
+
+enum SessionsCommand {
+    List,
+    Show {
+        session_id: String,
+        before: Option<i64>,
+    },
+    Delete {
+        session_id: String,
+        id: i64,
+    },
+    Clear {
+        session_id: String,
+    },
+}
+
+/// Parses `--config <path>` (or `--config=<path>`) and an optional
+/// subcommand from the command line.
+fn parse_args() -> Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut config = None;
+    let mut words = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if let Some(value) = arg.strip_prefix("--config=") {
+            config = Some(PathBuf::from(value));
+        } else if arg == "--config" || arg == "-c" {
+            let value = args.next().context("--config requires a path")?;
+            config = Some(PathBuf::from(value));
+        } else if arg.starts_with('-') {
+            anyhow::bail!("Unknown argument '{arg}'. {USAGE}");
+        } else {
+            words.push(arg);
+        }
+    }
+
+    let command = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => Command::Run,
+        ["personas", "check"] => Command::CheckPersonas,
+        ["migrate"] => Command::Migrate,
+        ["sessions"] | ["sessions", "list"] => Command::Sessions(SessionsCommand::List),
+        ["sessions", "show", session_id, ref before @ ..] if before.len() <= 1 => {
+            Command::Sessions(SessionsCommand::Show {
+                session_id: session_id.to_string(),
+                before: before.first().map(|id| parse_id(id)).transpose()?,
+            })
+        }
+        ["sessions", "delete", session_id, id] => Command::Sessions(SessionsCommand::Delete {
+            session_id: session_id.to_string(),
+            id: parse_id(id)?,
+        }),
+        ["sessions", "clear", session_id] => Command::Sessions(SessionsCommand::Clear {
+            session_id: session_id.to_string(),
+        }),
+        _ => anyhow::bail!("Unknown command '{}'. {USAGE}", words.join(" ")),
+    };
+    Ok(Args { config, command })
+}
+
+fn parse_id(raw: &str) -> Result<i64> {
+    raw.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid message id '{raw}'. {USAGE}"))
+}
+
+/// Browses or moderates the stored history without starting the bot.
+async fn sessions(config: &MemoryConfig, command: SessionsCommand) -> Result<()> {
+    let Some(mem) = build_memory(config).await? else {
+        anyhow::bail!("`chatbot sessions` needs a memory (MEMORY_TYPE is none)");
+    };
+
+    match command {
+        SessionsCommand::List => {
+            for session_id in mem.list_sessions().await? {
+                let count = mem.count(&session_id).await?;
+                println!("{session_id}\t{count} messages");
+            }
+        }
+        SessionsCommand::Show { session_id, before } => {
+            let page = mem.get_history_page(&session_id, before, PAGE_SIZE).await?;
+            for stored in &page {
+                println!(
+                    "[{}] {}: {}",
+                    stored.id, stored.message.role, stored.message.content
+                );
+            }
+            if let Some(oldest) = page.first()
+                && !mem
+                    .get_history_page(&session_id, Some(oldest.id), 1)
+                    .await?
+                    .is_empty()
+            {
+                println!("(older: chatbot sessions show {session_id} {})", oldest.id);
+            }
+        }
+        SessionsCommand::Delete { session_id, id } => {
+            if !mem.delete_message(&session_id, id).await? {
+                anyhow::bail!("Session '{session_id}' has no message {id}");
+            }
+            println!("Deleted message {id} from {session_id}");
+        }
+        SessionsCommand::Clear { session_id } => {
+            mem.clear_session(&session_id).await?;
+            println!("Cleared {session_id}");
+        }
+    }
+
+    // The in-memory history only lasts through its snapshot
+    mem.shutdown().await
+}
+
+/// Brings the Postgres schema up to date without starting the bot.
+async fn migrate(config: &MemoryConfig) -> Result<()> {
+    let MemoryConfig::Postgres { url } = config else {
+        anyhow::bail!(
+            "`chatbot migrate` applies to the postgres memory only (MEMORY_TYPE=postgres)"
+        );
+    };
+    memory::postgres::PostgresMemory::new(url).await?;
+
+    let version = memory::postgres::MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+    println!("Postgres schema is up to date (version {version})");
+    Ok(())
+}
+
+/// Validates the persona files, printing every problem; fails if there are any.
+/// Providers are checked only when the full configuration loads.
+fn check_personas(config_path: Option<PathBuf>) -> Result<()> {
+    let config = PersonaConfig::load(config_path.clone())?;
+    let (personas, mut problems) = persona::PersonaManager::check(&config.dir, &config.default)?;
+
+    // A persona naming an unconfigured provider would silently use the default
+    let configured: Option<Vec<&str>> = match Config::load(config_path) {
+        Ok(full) => Some(
+            full.llm
+                .providers
+                .iter()
+                .chain(&full.llm.extra)
+                .map(ProviderConfig::name)
+                .chain(["mock"])
+                .collect(),
+        ),
+        Err(e) => {
+            eprintln!("Skipping the provider check, the configuration is incomplete: {e:#}");
+            None
+        }
+    };
+    let mut personas: Vec<_> = personas.into_iter().collect();
+    personas.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (key, persona) in &personas {
+        if let Some(provider) = &persona.provider
+            && let Some(configured) = &configured
+            && !configured.contains(&provider.to_lowercase().as_str())
+        {
+            problems.push(format!(
+                "Persona '{key}' uses provider '{provider}', which is not configured under `llm`"
+            ));
+        }
+        // The model is only applied together with a provider
+        if let Some(model) = &persona.model
+            && persona.provider.is_none()
+        {
+            problems.push(format!(
+                "Persona '{key}' sets model '{model}' without a provider; it is ignored"
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{} personas in {} are valid.", personas.len(), config.dir);
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "Found {} problems in {}:\n  - {}",
+            problems.len(),
+            config.dir,
+            problems.join("\n  - ")
+        )
+    }
+}
+
+async fn build_memory(config: &MemoryConfig) -> Result<Option<Arc<dyn memory::Memory>>> {
+    let memory: Arc<dyn memory::Memory> = match config {
+        MemoryConfig::InMemory { capacity, snapshot } => {
+            tracing::info!(
+                "Initializing In-Memory history ({} messages per session)...",
+                capacity
+            );
+            let mem = memory::in_memory::InMemoryMemory::new(*capacity, snapshot.clone())
+                .context("Failed to init in-memory history")?;
+            Arc::new(mem)
+        }
+        MemoryConfig::Postgres { url } => {
             tracing::info!("Initializing Postgres Memory...");
-            let mem = memory::postgres::PostgresMemory::new()
+            let mem = memory::postgres::PostgresMemory::new(url)
                 .await
-                .expect("Failed to init Postgres memory");
-            Some(std::sync::Arc::new(mem))
+                .context("Failed to init Postgres memory")?;
+            Arc::new(mem)
         }
-        "redis" => {
+        MemoryConfig::Redis { url, max_messages } => {
             tracing::info!("Initializing Redis Memory...");
-            let mem = memory::redis::RedisMemory::new().expect("Failed to init Redis memory");
-            Some(std::sync::Arc::new(mem))
+            let mem = memory::redis::RedisMemory::new(url, *max_messages)
+                .context("Failed to init Redis memory")?;
+            Arc::new(mem)
+        }
+        MemoryConfig::Sqlite { path } => {
+            tracing::info!("Initializing SQLite Memory at {}...", path.display());
+            let mem = memory::sqlite::SqliteMemory::new(path)
+                .await
+                .context("Failed to init SQLite memory")?;
+            Arc::new(mem)
         }
-        _ => None,
+        MemoryConfig::None => return Ok(None),
     };
+    Ok(Some(memory))
+}
 
-    // 3. Initialize Persona Manager
-    tracing::info!("Loading Personas...");
-    let persona_manager = std::sync::Arc::new(
-        persona::PersonaManager::new("avatars", "default")
-            .expect("Failed to initialize Persona Manager"),
-    );
+struct ProviderClients {
+    llm: Arc<dyn llm::LLMClient>,
+    vision: Option<Arc<dyn llm::VisionClient>>,
+    voice: Option<Arc<dyn llm::VoiceClient>>,
+}
 
-    // 4. Initialize Bot Core
-    let bot = std::sync::Arc::new(Bot::new(
-        llm_client,
-        memory,
-        persona_manager,
-        vision_client,
-        voice_client,
-    ));
-
-    // 5. Initialize Platform Adapter
-    let platform_type = std::env::var("PLATFORM").unwrap_or_else(|_| "terminal".to_string());
-
-    if platform_type.as_str() == "onebot" {
-        let platform = platform::onebot::OneBotPlatform;
-        platform.run(bot).await?;
-    } else {
-        let platform = TerminalPlatform;
-        platform.run(bot).await?;
-    }
+fn build_provider(provider: &ProviderConfig, http: &HttpClient) -> ProviderClients {
+    let mut vision = None;
+    let mut voice = None;
 
-    Ok(())
+    let llm: Arc<dyn llm::LLMClient> = match provider {
+        ProviderConfig::DeepSeek(config) => Arc::new(llm::deepseek::new(config, http.clone())),
+        ProviderConfig::Doubao(config) => {
+            let client = Arc::new(llm::doubao::DoubaoClient::new(config, http.clone()));
+            vision = Some(client.clone() as Arc<dyn llm::VisionClient>);
+            voice = Some(client.clone() as Arc<dyn llm::VoiceClient>);
+            client
+        }
+        ProviderConfig::Grok(config) => Arc::new(llm::grok::new(config, http.clone())),
+        ProviderConfig::OpenAi(config) => {
+            Arc::new(llm::openai_compat::OpenAICompatClient::from_config(
+                "OpenAI-compatible",
+                config,
+                http.clone(),
+            ))
+        }
+        ProviderConfig::Mock => Arc::new(MockLLM),
+    };
+
+    ProviderClients { llm, vision, voice }
 }
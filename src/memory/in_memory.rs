@@ -0,0 +1,395 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    memory::{Memory, StoredMessage},
+    prompt::Message,
+};
+
+/// Chat history kept in process, the newest `capacity` messages per session.
+/// With a snapshot file, sessions survive restarts: they are written to it
+/// on shutdown and read back on startup.
+pub struct InMemoryMemory {
+    capacity: usize,
+    snapshot: Option<PathBuf>,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Session {
+    #[serde(default)]
+    messages: VecDeque<StoredMessage>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// Id of the next message; ids are not reused after trimming or deletion.
+    #[serde(default)]
+    next_id: i64,
+}
+
+/// One line of the snapshot file.
+#[derive(Serialize, Deserialize)]
+struct SnapshotLine<S> {
+    session_id: String,
+    #[serde(flatten)]
+    session: S,
+}
+
+impl InMemoryMemory {
+    /// Restores the sessions from `snapshot` when the file exists.
+    pub fn new(capacity: usize, snapshot: Option<PathBuf>) -> Result<Self> {
+        let mut sessions = HashMap::new();
+
+        if let Some(path) = snapshot.as_ref().filter(|path| path.exists()) {
+            let content = fs::read_to_string(path).context(format!(
+                "Failed to read memory snapshot: {}",
+                path.display()
+            ))?;
+            for (index, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let SnapshotLine {
+                    session_id,
+                    mut session,
+                } = serde_json::from_str::<SnapshotLine<Session>>(line).context(format!(
+                    "Corrupt memory snapshot {} at line {}",
+                    path.display(),
+                    index + 1
+                ))?;
+                trim(&mut session.messages, capacity);
+                if let Some(last) = session.messages.back() {
+                    session.next_id = session.next_id.max(last.id.saturating_add(1));
+                }
+                sessions.insert(session_id, session);
+            }
+            tracing::info!(
+                "Restored {} sessions from {}",
+                sessions.len(),
+                path.display()
+            );
+        }
+
+        Ok(Self {
+            capacity,
+            snapshot,
+            sessions: RwLock::new(sessions),
+        })
+    }
+
+    /// Writes every session to the snapshot file, replacing it at once so an
+    /// interrupted write leaves the previous snapshot intact.
+    fn save_snapshot(&self, path: &Path) -> Result<()> {
+        let sessions = self.sessions.read().expect("memory lock poisoned");
+        let mut ids: Vec<&String> = sessions.keys().collect();
+        ids.sort();
+
+        let tmp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(
+            fs::File::create(&tmp).context(format!("Failed to create {}", tmp.display()))?,
+        );
+        for session_id in ids {
+            let line = SnapshotLine {
+                session_id: session_id.clone(),
+                session: &sessions[session_id],
+            };
+            serde_json::to_writer(&mut file, &line)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        drop(file);
+        fs::rename(&tmp, path).context(format!("Failed to write {}", path.display()))?;
+
+        tracing::info!("Saved {} sessions to {}", sessions.len(), path.display());
+        Ok(())
+    }
+}
+
+/// Drops the oldest messages beyond `capacity`.
+fn trim(messages: &mut VecDeque<StoredMessage>, capacity: usize) {
+    while messages.len() > capacity {
+        messages.pop_front();
+    }
+}
+
+#[async_trait]
+impl Memory for InMemoryMemory {
+    async fn get_history(&self, session_id: &str, limit: usize) -> Result<Vec<Message>> {
+        Ok(self
+            .sessions
+            .read()
+            .expect("memory lock poisoned")
+            .get(session_id)
+            .map(|session| {
+                let skip = session.messages.len().saturating_sub(limit);
+                session
+                    .messages
+                    .iter()
+                    .skip(skip)
+                    .map(|stored| stored.message.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn add_message(&self, session_id: &str, message: Message) -> Result<()> {
+        let mut sessions = self.sessions.write().expect("memory lock poisoned");
+        let session = sessions.entry(session_id.to_string()).or_default();
+        session.messages.push_back(StoredMessage {
+            id: session.next_id,
+            message,
+        });
+        session.next_id += 1;
+        trim(&mut session.messages, self.capacity);
+        Ok(())
+    }
+
+    async fn get_metadata(&self, session_id: &str, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .sessions
+            .read()
+            .expect("memory lock poisoned")
+            .get(session_id)
+            .and_then(|session| session.metadata.get(key).cloned()))
+    }
+
+    async fn set_metadata(&self, session_id: &str, key: &str, value: &str) -> Result<()> {
+        self.sessions
+            .write()
+            .expect("memory lock poisoned")
+            .entry(session_id.to_string())
+            .or_default()
+            .metadata
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn clear_session(&self, session_id: &str) -> Result<()> {
+        self.sessions
+            .write()
+            .expect("memory lock poisoned")
+            .remove(session_id);
+        Ok(())
+    }
+
+    async fn delete_message(&self, session_id: &str, id: i64) -> Result<bool> {
+        let mut sessions = self.sessions.write().expect("memory lock poisoned");
+        let Some(messages) = sessions.get_mut(session_id).map(|s| &mut s.messages) else {
+            return Ok(false);
+        };
+        let Ok(index) = messages.binary_search_by_key(&id, |stored| stored.id) else {
+            return Ok(false);
+        };
+        messages.remove(index);
+        Ok(true)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        let mut sessions: Vec<String> = self
+            .sessions
+            .read()
+            .expect("memory lock poisoned")
+            .iter()
+            .filter(|(_, session)| !session.messages.is_empty())
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        sessions.sort();
+        Ok(sessions)
+    }
+
+    async fn get_history_page(
+        &self,
+        session_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let sessions = self.sessions.read().expect("memory lock poisoned");
+        let Some(messages) = sessions.get(session_id).map(|s| &s.messages) else {
+            return Ok(Vec::new());
+        };
+        // Ids ascend through the buffer
+        let end = before.map_or(messages.len(), |before| {
+            messages.partition_point(|stored| stored.id < before)
+        });
+        let start = end.saturating_sub(limit);
+        Ok(messages.range(start..end).cloned().collect())
+    }
+
+    async fn count(&self, session_id: &str) -> Result<usize> {
+        Ok(self
+            .sessions
+            .read()
+            .expect("memory lock poisoned")
+            .get(session_id)
+            .map_or(0, |session| session.messages.len()))
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        match &self.snapshot {
+            Some(path) => self.save_snapshot(path),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fill(memory: &InMemoryMemory, session_id: &str, count: usize) {
+        for i in 0..count {
+            memory
+                .add_message(session_id, Message::user(&format!("m{i}"), None))
+                .await
+                .unwrap();
+        }
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    fn ids(page: &[StoredMessage]) -> Vec<i64> {
+        page.iter().map(|stored| stored.id).collect()
+    }
+
+    #[tokio::test]
+    async fn trims_to_capacity_without_reusing_ids() {
+        let memory = InMemoryMemory::new(3, None).unwrap();
+        fill(&memory, "a", 5).await;
+
+        let history = memory.get_history("a", 10).await.unwrap();
+        assert_eq!(contents(&history), ["m2", "m3", "m4"]);
+        assert_eq!(
+            contents(&memory.get_history("a", 2).await.unwrap()),
+            ["m3", "m4"]
+        );
+        let page = memory.get_history_page("a", None, 10).await.unwrap();
+        assert_eq!(ids(&page), [2, 3, 4]);
+        assert_eq!(memory.count("a").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn pages_and_deletes_by_id() {
+        let memory = InMemoryMemory::new(10, None).unwrap();
+        fill(&memory, "a", 5).await;
+
+        assert_eq!(
+            ids(&memory.get_history_page("a", None, 2).await.unwrap()),
+            [3, 4]
+        );
+        assert_eq!(
+            ids(&memory.get_history_page("a", Some(3), 2).await.unwrap()),
+            [1, 2]
+        );
+        assert_eq!(
+            ids(&memory.get_history_page("a", Some(1), 5).await.unwrap()),
+            [0]
+        );
+
+        assert!(memory.delete_message("a", 2).await.unwrap());
+        assert!(!memory.delete_message("a", 2).await.unwrap());
+        assert!(!memory.delete_message("b", 1).await.unwrap());
+        // Paging still works around the gap
+        assert_eq!(
+            ids(&memory.get_history_page("a", Some(4), 2).await.unwrap()),
+            [1, 3]
+        );
+        assert_eq!(
+            ids(&memory.get_history_page("a", Some(2), 5).await.unwrap()),
+            [0, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn clearing_removes_messages_and_metadata() {
+        let memory = InMemoryMemory::new(10, None).unwrap();
+        fill(&memory, "a", 2).await;
+        fill(&memory, "b", 1).await;
+        memory.set_metadata("a", "persona", "mia").await.unwrap();
+
+        memory.clear_session("a").await.unwrap();
+        assert_eq!(memory.count("a").await.unwrap(), 0);
+        assert_eq!(memory.get_metadata("a", "persona").await.unwrap(), None);
+        assert_eq!(memory.list_sessions().await.unwrap(), ["b"]);
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memory.jsonl");
+
+        let memory = InMemoryMemory::new(10, Some(path.clone())).unwrap();
+        fill(&memory, "a", 3).await;
+        fill(&memory, "b", 1).await;
+        memory.set_metadata("b", "persona", "mia").await.unwrap();
+        // The newest message is gone, but its id must not be handed out again
+        assert!(memory.delete_message("a", 2).await.unwrap());
+        memory.shutdown().await.unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        // Restored with a smaller capacity, which trims on load
+        let restored = InMemoryMemory::new(1, Some(path.clone())).unwrap();
+        assert_eq!(restored.list_sessions().await.unwrap(), ["a", "b"]);
+        assert_eq!(
+            contents(&restored.get_history("a", 10).await.unwrap()),
+            ["m1"]
+        );
+        assert_eq!(
+            restored
+                .get_metadata("b", "persona")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("mia")
+        );
+        restored
+            .add_message("a", Message::assistant("new"))
+            .await
+            .unwrap();
+        let page = restored.get_history_page("a", None, 10).await.unwrap();
+        assert_eq!(ids(&page), [3]);
+    }
+
+    #[tokio::test]
+    async fn restores_next_id_from_older_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memory.jsonl");
+        // Written without `next_id`, as before ids were tracked
+        let message = serde_json::to_string(&StoredMessage {
+            id: 7,
+            message: Message::user("old", None),
+        })
+        .unwrap();
+        fs::write(
+            &path,
+            format!("{{\"session_id\":\"a\",\"messages\":[{message}]}}\n\n"),
+        )
+        .unwrap();
+
+        let memory = InMemoryMemory::new(10, Some(path)).unwrap();
+        memory
+            .add_message("a", Message::assistant("new"))
+            .await
+            .unwrap();
+        let page = memory.get_history_page("a", None, 10).await.unwrap();
+        assert_eq!(ids(&page), [7, 8]);
+    }
+
+    #[test]
+    fn rejects_corrupt_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memory.jsonl");
+        fs::write(&path, "{\"session_id\":\"a\"}\nnot json\n").unwrap();
+        let error = InMemoryMemory::new(10, Some(path)).err().unwrap();
+        assert!(format!("{error:#}").contains("at line 2"));
+    }
+}
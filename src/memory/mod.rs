@@ -1,16 +1,61 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
 use crate::prompt::Message;
 
+/// A message as stored, with the id that addresses it within its session.
+/// Ids grow with insertion order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: i64,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
 #[async_trait]
 pub trait Memory: Send + Sync {
-    // Retrieve recent history for context
-    async fn get_history(&self, session_id: &str) -> Result<Vec<Message>>;
+    // Retrieve the newest `limit` messages for context, oldest first
+    async fn get_history(&self, session_id: &str, limit: usize) -> Result<Vec<Message>>;
 
     // Save a new message to the history
     async fn add_message(&self, session_id: &str, message: Message) -> Result<()>;
+
+    // Read a per-session value stored alongside the history (e.g. the running summary)
+    async fn get_metadata(&self, session_id: &str, key: &str) -> Result<Option<String>>;
+
+    // Create or replace a per-session value
+    async fn set_metadata(&self, session_id: &str, key: &str, value: &str) -> Result<()>;
+
+    // Remove a session's history and metadata
+    async fn clear_session(&self, session_id: &str) -> Result<()>;
+
+    // Remove one message; false if the session has no message with that id
+    async fn delete_message(&self, session_id: &str, id: i64) -> Result<bool>;
+
+    // Sessions that have stored messages, sorted
+    async fn list_sessions(&self) -> Result<Vec<String>>;
+
+    // Up to `limit` messages older than `before` (or the newest ones), oldest first
+    async fn get_history_page(
+        &self,
+        session_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>>;
+
+    // Number of stored messages in a session
+    async fn count(&self, session_id: &str) -> Result<usize>;
+
+    // Persist anything still held in process before the bot exits
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
+pub mod in_memory;
 pub mod postgres;
 pub mod redis;
+pub mod sqlite;
+pub mod summary;
+pub mod tasks;
@@ -1,35 +1,32 @@
-use std::env;
-
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use sqlx::{Pool, Postgres, migrate::Migrator, postgres::PgPoolOptions};
+
+use crate::{
+    memory::{Memory, StoredMessage},
+    prompt::Message,
+};
 
-use crate::{memory::Memory, prompt::Message};
+/// Versioned schema in `migrations/postgres`, applied on startup and by
+/// `chatbot migrate`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
 
 pub struct PostgresMemory {
     pool: Pool<Postgres>,
 }
 
 impl PostgresMemory {
-    pub async fn new() -> Result<Self> {
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    /// Connects and brings the schema up to date.
+    pub async fn new(database_url: &str) -> Result<Self> {
         let pool = PgPoolOptions::new()
             .max_connections(5)
-            .connect(&database_url)
+            .connect(database_url)
             .await?;
 
-        // Initialize schema
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id SERIAL PRIMARY KEY,
-                session_id VARCHAR NOT NULL,
-                role VARCHAR NOT NULL,
-                content TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-        )
-        .execute(&pool)
-        .await?;
+        MIGRATOR
+            .run(&pool)
+            .await
+            .context("Failed to migrate the Postgres schema")?;
 
         Ok(Self { pool })
     }
@@ -37,41 +34,163 @@ impl PostgresMemory {
 
 #[async_trait]
 impl Memory for PostgresMemory {
-    async fn get_history(&self, session_id: &str) -> Result<Vec<Message>> {
+    async fn get_history(&self, session_id: &str, limit: usize) -> Result<Vec<Message>> {
+        // Newest rows first so the (session_id, created_at) index bounds the scan;
+        // the id column (not its BIGINT alias) orders rows created in the same instant
         let rows = sqlx::query_as::<_, MessageRecord>(
-            "SELECT role, content, user_id FROM messages WHERE session_id = $1 ORDER BY created_at ASC",
+            "SELECT * FROM (
+                SELECT id::BIGINT AS id, role, content, user_id, tool_calls, tool_call_id,
+                       created_at
+                FROM messages WHERE session_id = $1
+                ORDER BY created_at DESC, messages.id DESC LIMIT $2
+             ) AS recent ORDER BY created_at, id",
         )
         .bind(session_id)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(rows
-            .into_iter()
-            .map(|r| Message {
-                role: r.role,
-                content: r.content,
-                user_id: r.user_id,
-            })
-            .collect())
+        rows.into_iter()
+            .map(|r| Ok(r.into_stored()?.message))
+            .collect()
     }
 
     async fn add_message(&self, session_id: &str, message: Message) -> Result<()> {
         sqlx::query(
-            "INSERT INTO messages (session_id, role, content, user_id) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO messages (session_id, role, content, user_id, tool_calls, tool_call_id)
+             VALUES ($1, $2, $3, $4, $5, $6)",
         )
         .bind(session_id)
         .bind(&message.role)
         .bind(&message.content)
         .bind(&message.user_id)
+        .bind(
+            message
+                .tool_calls
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+        )
+        .bind(&message.tool_call_id)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
+
+    async fn get_metadata(&self, session_id: &str, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query_scalar::<_, String>(
+            "SELECT value FROM session_metadata WHERE session_id = $1 AND key = $2",
+        )
+        .bind(session_id)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(value)
+    }
+
+    async fn set_metadata(&self, session_id: &str, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO session_metadata (session_id, key, value) VALUES ($1, $2, $3)
+             ON CONFLICT (session_id, key)
+             DO UPDATE SET value = EXCLUDED.value, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(session_id)
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn clear_session(&self, session_id: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM messages WHERE session_id = $1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM session_metadata WHERE session_id = $1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, session_id: &str, id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM messages WHERE session_id = $1 AND id = $2")
+            .bind(session_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        let sessions = sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT session_id FROM messages ORDER BY session_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    async fn get_history_page(
+        &self,
+        session_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let rows = sqlx::query_as::<_, MessageRecord>(
+            "SELECT * FROM (
+                SELECT id::BIGINT AS id, role, content, user_id, tool_calls, tool_call_id
+                FROM messages WHERE session_id = $1 AND ($2::BIGINT IS NULL OR id < $2)
+                ORDER BY id DESC LIMIT $3
+             ) AS page ORDER BY id ASC",
+        )
+        .bind(session_id)
+        .bind(before)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(MessageRecord::into_stored).collect()
+    }
+
+    async fn count(&self, session_id: &str) -> Result<usize> {
+        let count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM messages WHERE session_id = $1")
+                .bind(session_id)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(usize::try_from(count)?)
+    }
 }
 
 #[derive(sqlx::FromRow)]
 struct MessageRecord {
+    id: i64,
     role: String,
     content: String,
     user_id: Option<String>,
+    tool_calls: Option<String>,
+    tool_call_id: Option<String>,
+}
+
+impl MessageRecord {
+    fn into_stored(self) -> Result<StoredMessage> {
+        let tool_calls = self
+            .tool_calls
+            .map(|raw| serde_json::from_str(&raw))
+            .transpose()?;
+        Ok(StoredMessage {
+            id: self.id,
+            message: Message {
+                role: self.role,
+                content: self.content,
+                user_id: self.user_id,
+                tool_calls,
+                tool_call_id: self.tool_call_id,
+            },
+        })
+    }
 }
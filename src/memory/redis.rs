@@ -1,50 +1,238 @@
-use std::env;
-
 use anyhow::Result;
 use async_trait::async_trait;
 use redis::AsyncCommands;
 
-use crate::{memory::Memory, prompt::Message};
+use crate::{
+    memory::{Memory, StoredMessage},
+    prompt::Message,
+};
+
+/// Messages read per round trip while walking a history list backwards.
+const SCAN_CHUNK: isize = 100;
+
+/// Seconds a session's history is kept after its last message.
+const SESSION_TTL: i64 = 3600 * 24;
+
+/// Seconds the metadata (summary, persona, state) and the id counter are kept
+/// after the session was last used. They outlive the history so the summary
+/// keeps older conversations, and ids never start over underneath it.
+const METADATA_TTL: i64 = 3600 * 24 * 30;
+
+/// Numbers, appends, trims and refreshes the expiry of a message (and of the
+/// session's metadata) in one atomic step, so concurrent writers cannot
+/// interleave. The id goes into the message JSON (`ARGV[1]`, an object) as
+/// its first field.
+const ADD_MESSAGE: &str = r#"
+local id = redis.call('INCR', KEYS[2])
+redis.call('RPUSH', KEYS[1], '{"id":' .. id .. ',' .. string.sub(ARGV[1], 2))
+local max = tonumber(ARGV[2])
+if max > 0 then
+    redis.call('LTRIM', KEYS[1], -max, -1)
+end
+redis.call('EXPIRE', KEYS[1], ARGV[3])
+redis.call('EXPIRE', KEYS[2], ARGV[4])
+redis.call('EXPIRE', KEYS[3], ARGV[4])
+return id
+"#;
 
 pub struct RedisMemory {
     client: redis::Client,
+    /// Messages kept per session list; older ones are trimmed. `0` keeps all.
+    max_messages: usize,
+    add_message: redis::Script,
 }
 
 impl RedisMemory {
-    pub fn new() -> Result<Self> {
-        let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+    pub fn new(redis_url: &str, max_messages: usize) -> Result<Self> {
         let client = redis::Client::open(redis_url)?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            max_messages,
+            add_message: redis::Script::new(ADD_MESSAGE),
+        })
     }
 }
 
 #[async_trait]
 impl Memory for RedisMemory {
-    async fn get_history(&self, session_id: &str) -> Result<Vec<Message>> {
+    async fn get_history(&self, session_id: &str, limit: usize) -> Result<Vec<Message>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
         let mut conn = self.client.get_async_connection().await?;
         let key = format!("chat:{session_id}");
 
-        let raw_messages: Vec<String> = conn.lrange(&key, 0, -1).await?;
+        let start = -isize::try_from(limit).unwrap_or(isize::MAX);
+        let raw_messages: Vec<String> = conn.lrange(&key, start, -1).await?;
 
-        let mut messages = Vec::new();
-        for raw in raw_messages {
-            if let Ok(msg) = serde_json::from_str::<Message>(&raw) {
-                messages.push(msg);
-            }
-        }
-
-        Ok(messages)
+        Ok(raw_messages
+            .iter()
+            .filter_map(|raw| parse_entry(raw))
+            .map(|entry| entry.message)
+            .collect())
     }
 
     async fn add_message(&self, session_id: &str, message: Message) -> Result<()> {
         let mut conn = self.client.get_async_connection().await?;
         let key = format!("chat:{session_id}");
+        let seq_key = format!("chat:{session_id}:seq");
+        let meta_key = format!("chat:{session_id}:meta");
 
+        // A struct, so always a non-empty JSON object
         let json = serde_json::to_string(&message)?;
-        let _: () = conn.rpush(&key, json).await?;
-        // Optional: Set expire
-        let _: () = conn.expire(&key, 3600 * 24).await?; // 24 hours
+        let _: i64 = self
+            .add_message
+            .key(&key)
+            .key(&seq_key)
+            .key(&meta_key)
+            .arg(json)
+            .arg(self.max_messages)
+            .arg(SESSION_TTL)
+            .arg(METADATA_TTL)
+            .invoke_async(&mut conn)
+            .await?;
 
         Ok(())
     }
+
+    async fn get_metadata(&self, session_id: &str, key: &str) -> Result<Option<String>> {
+        let mut conn = self.client.get_async_connection().await?;
+        let value: Option<String> = conn.hget(format!("chat:{session_id}:meta"), key).await?;
+        Ok(value)
+    }
+
+    async fn set_metadata(&self, session_id: &str, key: &str, value: &str) -> Result<()> {
+        let mut conn = self.client.get_async_connection().await?;
+        let meta_key = format!("chat:{session_id}:meta");
+        let _: () = redis::pipe()
+            .atomic()
+            .hset(&meta_key, key, value)
+            .ignore()
+            .expire(&meta_key, METADATA_TTL)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_session(&self, session_id: &str) -> Result<()> {
+        let mut conn = self.client.get_async_connection().await?;
+        let _: () = conn
+            .del(&[
+                format!("chat:{session_id}"),
+                format!("chat:{session_id}:meta"),
+                format!("chat:{session_id}:seq"),
+            ])
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, session_id: &str, id: i64) -> Result<bool> {
+        let mut conn = self.client.get_async_connection().await?;
+        let key = format!("chat:{session_id}");
+
+        // No message can carry the largest id, which comes from user input
+        let Some(before) = id.checked_add(1) else {
+            return Ok(false);
+        };
+        let newest = entries_before(&mut conn, &key, Some(before), 1).await?;
+        let Some((raw, _)) = newest.into_iter().find(|(_, entry)| entry.id == id) else {
+            return Ok(false);
+        };
+        let removed: usize = conn.lrem(&key, 1, raw).await?;
+        Ok(removed > 0)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        let mut conn = self.client.get_async_connection().await?;
+        let mut sessions = Vec::new();
+        let mut cursor: u64 = 0;
+        // Only the history lists, not the metadata hashes or id counters
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg("chat:*")
+                .arg("TYPE")
+                .arg("list")
+                .query_async(&mut conn)
+                .await?;
+            sessions.extend(
+                keys.iter()
+                    .filter_map(|key| key.strip_prefix("chat:"))
+                    .map(str::to_string),
+            );
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        // SCAN may return a key more than once
+        sessions.sort();
+        sessions.dedup();
+        Ok(sessions)
+    }
+
+    async fn get_history_page(
+        &self,
+        session_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let mut conn = self.client.get_async_connection().await?;
+        let key = format!("chat:{session_id}");
+
+        let mut page: Vec<StoredMessage> = entries_before(&mut conn, &key, before, limit)
+            .await?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        page.reverse();
+        Ok(page)
+    }
+
+    async fn count(&self, session_id: &str) -> Result<usize> {
+        let mut conn = self.client.get_async_connection().await?;
+        let count: usize = conn.llen(format!("chat:{session_id}")).await?;
+        Ok(count)
+    }
+}
+
+/// A list entry with its id. Entries written before messages had ids read
+/// as id 0.
+fn parse_entry(raw: &str) -> Option<StoredMessage> {
+    serde_json::from_str::<StoredMessage>(raw).ok().or_else(|| {
+        serde_json::from_str::<Message>(raw)
+            .ok()
+            .map(|message| StoredMessage { id: 0, message })
+    })
+}
+
+/// Up to `limit` entries older than `before`, newest first, with their raw
+/// form. Walks the list from its end so recent pages stay cheap.
+async fn entries_before(
+    conn: &mut redis::aio::Connection,
+    key: &str,
+    before: Option<i64>,
+    limit: usize,
+) -> Result<Vec<(String, StoredMessage)>> {
+    let mut entries = Vec::new();
+    let mut end: isize = conn.llen(key).await?;
+    while end > 0 && entries.len() < limit {
+        let start = end.saturating_sub(SCAN_CHUNK).max(0);
+        let chunk: Vec<String> = conn.lrange(key, start, end - 1).await?;
+        for raw in chunk.into_iter().rev() {
+            let Some(entry) = parse_entry(&raw) else {
+                continue;
+            };
+            if before.is_none_or(|before| entry.id < before) {
+                entries.push((raw, entry));
+                if entries.len() == limit {
+                    break;
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(entries)
 }
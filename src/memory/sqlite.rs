@@ -0,0 +1,329 @@
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{
+    Pool, Sqlite,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+
+use crate::{
+    memory::{Memory, StoredMessage},
+    prompt::Message,
+};
+
+/// Chat history in a local `SQLite` file, for deployments without a database
+/// server. The schema mirrors [`PostgresMemory`](crate::memory::postgres::PostgresMemory).
+pub struct SqliteMemory {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteMemory {
+    /// Opens the database at `path`, creating the file and schema if needed.
+    pub async fn new(path: &Path) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await?;
+
+        // Initialize schema
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                user_id TEXT,
+                tool_calls TEXT,
+                tool_call_id TEXT,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_session ON messages (session_id, id)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS session_metadata (
+                session_id TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (session_id, key)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Memory for SqliteMemory {
+    async fn get_history(&self, session_id: &str, limit: usize) -> Result<Vec<Message>> {
+        // CURRENT_TIMESTAMP has one-second resolution, so order by insertion
+        let rows = sqlx::query_as::<_, MessageRecord>(
+            "SELECT * FROM (
+                SELECT id, role, content, user_id, tool_calls, tool_call_id FROM messages
+                WHERE session_id = ?1 ORDER BY id DESC LIMIT ?2
+             ) ORDER BY id ASC",
+        )
+        .bind(session_id)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|r| Ok(r.into_stored()?.message))
+            .collect()
+    }
+
+    async fn add_message(&self, session_id: &str, message: Message) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO messages (session_id, role, content, user_id, tool_calls, tool_call_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )
+        .bind(session_id)
+        .bind(&message.role)
+        .bind(&message.content)
+        .bind(&message.user_id)
+        .bind(
+            message
+                .tool_calls
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+        )
+        .bind(&message.tool_call_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_metadata(&self, session_id: &str, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query_scalar::<_, String>(
+            "SELECT value FROM session_metadata WHERE session_id = ?1 AND key = ?2",
+        )
+        .bind(session_id)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(value)
+    }
+
+    async fn set_metadata(&self, session_id: &str, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO session_metadata (session_id, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (session_id, key)
+             DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(session_id)
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn clear_session(&self, session_id: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM messages WHERE session_id = ?1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM session_metadata WHERE session_id = ?1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, session_id: &str, id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM messages WHERE session_id = ?1 AND id = ?2")
+            .bind(session_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        let sessions = sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT session_id FROM messages ORDER BY session_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    async fn get_history_page(
+        &self,
+        session_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let rows = sqlx::query_as::<_, MessageRecord>(
+            "SELECT * FROM (
+                SELECT id, role, content, user_id, tool_calls, tool_call_id FROM messages
+                WHERE session_id = ?1 AND (?2 IS NULL OR id < ?2)
+                ORDER BY id DESC LIMIT ?3
+             ) ORDER BY id ASC",
+        )
+        .bind(session_id)
+        .bind(before)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(MessageRecord::into_stored).collect()
+    }
+
+    async fn count(&self, session_id: &str) -> Result<usize> {
+        let count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM messages WHERE session_id = ?1")
+                .bind(session_id)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(usize::try_from(count)?)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MessageRecord {
+    id: i64,
+    role: String,
+    content: String,
+    user_id: Option<String>,
+    tool_calls: Option<String>,
+    tool_call_id: Option<String>,
+}
+
+impl MessageRecord {
+    fn into_stored(self) -> Result<StoredMessage> {
+        let tool_calls = self
+            .tool_calls
+            .map(|raw| serde_json::from_str(&raw))
+            .transpose()?;
+        Ok(StoredMessage {
+            id: self.id,
+            message: Message {
+                role: self.role,
+                content: self.content,
+                user_id: self.user_id,
+                tool_calls,
+                tool_call_id: self.tool_call_id,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh database; the directory must outlive the memory.
+    async fn memory() -> (tempfile::TempDir, SqliteMemory) {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = SqliteMemory::new(&dir.path().join("chatbot.db"))
+            .await
+            .unwrap();
+        (dir, memory)
+    }
+
+    async fn fill(memory: &SqliteMemory, session_id: &str, count: usize) {
+        for i in 0..count {
+            memory
+                .add_message(session_id, Message::user(&format!("m{i}"), None))
+                .await
+                .unwrap();
+        }
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn history_is_the_latest_messages_in_order() {
+        let (_dir, memory) = memory().await;
+        fill(&memory, "a", 5).await;
+        fill(&memory, "b", 1).await;
+
+        let history = memory.get_history("a", 3).await.unwrap();
+        assert_eq!(contents(&history), ["m2", "m3", "m4"]);
+        assert_eq!(memory.get_history("a", 10).await.unwrap().len(), 5);
+        assert!(memory.get_history("missing", 10).await.unwrap().is_empty());
+        assert_eq!(memory.count("a").await.unwrap(), 5);
+        assert_eq!(memory.count("b").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn pages_walk_backwards_by_id() {
+        let (_dir, memory) = memory().await;
+        fill(&memory, "a", 5).await;
+
+        let latest = memory.get_history_page("a", None, 2).await.unwrap();
+        let page: Vec<Message> = latest.iter().map(|s| s.message.clone()).collect();
+        assert_eq!(contents(&page), ["m3", "m4"]);
+        assert!(latest[0].id < latest[1].id);
+
+        let older = memory
+            .get_history_page("a", Some(latest[0].id), 10)
+            .await
+            .unwrap();
+        let page: Vec<Message> = older.iter().map(|s| s.message.clone()).collect();
+        assert_eq!(contents(&page), ["m0", "m1", "m2"]);
+    }
+
+    #[tokio::test]
+    async fn deletes_only_within_the_session() {
+        let (_dir, memory) = memory().await;
+        fill(&memory, "a", 3).await;
+        let id = memory.get_history_page("a", None, 3).await.unwrap()[1].id;
+
+        assert!(!memory.delete_message("b", id).await.unwrap());
+        assert!(memory.delete_message("a", id).await.unwrap());
+        assert!(!memory.delete_message("a", id).await.unwrap());
+        let history = memory.get_history("a", 10).await.unwrap();
+        assert_eq!(contents(&history), ["m0", "m2"]);
+    }
+
+    #[tokio::test]
+    async fn clearing_removes_messages_and_metadata() {
+        let (_dir, memory) = memory().await;
+        fill(&memory, "a", 2).await;
+        fill(&memory, "b", 1).await;
+        memory.set_metadata("a", "persona", "mia").await.unwrap();
+        memory.set_metadata("a", "persona", "kai").await.unwrap();
+        memory.set_metadata("b", "persona", "mia").await.unwrap();
+        assert_eq!(
+            memory
+                .get_metadata("a", "persona")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("kai")
+        );
+
+        memory.clear_session("a").await.unwrap();
+        assert_eq!(memory.count("a").await.unwrap(), 0);
+        assert_eq!(memory.get_metadata("a", "persona").await.unwrap(), None);
+        assert_eq!(memory.list_sessions().await.unwrap(), ["b"]);
+        assert_eq!(
+            memory
+                .get_metadata("b", "persona")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("mia")
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt::Write, sync::Arc};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::SummaryConfig,
+    llm::{GenerationOptions, LLMClient},
+    memory::{Memory, StoredMessage, tasks::SessionTasks},
+    prompt::Message,
+};
+
+const SUMMARY_KEY: &str = "summary";
+
+const SUMMARY_INSTRUCTIONS: &str = "You maintain a running summary of a conversation between a \
+user and an AI companion. Merge the existing summary with the new messages into one concise \
+summary. Keep names, facts about the user, preferences, promises and unresolved topics; drop \
+small talk. Write in the same language as the conversation and reply with the summary only.";
+
+// Summaries should stick to what was said rather than follow the persona's style.
+const SUMMARY_TEMPERATURE: f32 = 0.3;
+
+/// Running summary of a session, stored in the session metadata.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub content: String,
+    /// Id of the newest message folded into `content`.
+    #[serde(default)]
+    pub through: Option<i64>,
+}
+
+/// Condenses old history into a [`SessionSummary`] so long-lived sessions keep
+/// their memory without sending every message to the LLM.
+pub struct Summarizer {
+    llm: Arc<dyn LLMClient>,
+    /// Unsummarized messages allowed before older ones are condensed.
+    threshold: usize,
+    /// Newest messages that are never folded into the summary.
+    keep_recent: usize,
+    /// At most one condensation per session at a time.
+    tasks: SessionTasks,
+}
+
+impl Summarizer {
+    pub fn new(llm: Arc<dyn LLMClient>, threshold: usize, keep_recent: usize) -> Self {
+        Self {
+            llm,
+            threshold,
+            keep_recent: keep_recent.min(threshold),
+            tasks: SessionTasks::default(),
+        }
+    }
+
+    /// Returns `None` when summarization is disabled (`threshold` of `0`).
+    pub fn from_config(llm: Arc<dyn LLMClient>, config: SummaryConfig) -> Option<Self> {
+        (config.threshold > 0).then(|| Self::new(llm, config.threshold, config.keep_recent))
+    }
+
+    /// Reads the newest `limit` messages and splits them into the stored
+    /// summary (as a system message) and the messages it does not cover yet.
+    /// When too many messages are uncovered, a background task condenses the
+    /// oldest of them for the following turns.
+    pub async fn prepare(
+        self: &Arc<Self>,
+        memory: &Arc<dyn Memory>,
+        session_id: &str,
+        limit: usize,
+    ) -> Result<(Option<Message>, Vec<Message>)> {
+        let mut summary = load(memory.as_ref(), session_id).await?;
+        let history = memory.get_history_page(session_id, None, limit).await?;
+        if let Some(through) = summary.through
+            && history.last().is_some_and(|newest| newest.id < through)
+        {
+            // The ids started over underneath the summary. An empty history
+            // says nothing: it may just have expired while the summary is kept.
+            summary = SessionSummary::default();
+            memory
+                .set_metadata(session_id, SUMMARY_KEY, &serde_json::to_string(&summary)?)
+                .await?;
+        }
+
+        let recent: Vec<StoredMessage> = history
+            .into_iter()
+            .filter(|stored| summary.through.is_none_or(|through| stored.id > through))
+            .collect();
+
+        if recent.len() > self.threshold {
+            let to_fold = recent[..recent.len() - self.keep_recent].to_vec();
+            self.spawn_condense(memory.clone(), session_id, summary.clone(), to_fold);
+        }
+
+        let system = (!summary.content.is_empty()).then(|| {
+            Message::system(&format!(
+                "Summary of the earlier conversation:\n{}",
+                summary.content
+            ))
+        });
+
+        Ok((
+            system,
+            recent.into_iter().map(|stored| stored.message).collect(),
+        ))
+    }
+
+    fn spawn_condense(
+        self: &Arc<Self>,
+        memory: Arc<dyn Memory>,
+        session_id: &str,
+        summary: SessionSummary,
+        to_fold: Vec<StoredMessage>,
+    ) {
+        let this = self.clone();
+        let task_session = session_id.to_string();
+        self.tasks.spawn(session_id, true, async move {
+            if let Err(e) = this
+                .condense(memory.as_ref(), &task_session, summary, &to_fold)
+                .await
+            {
+                tracing::error!("Failed to summarize session {}: {}", task_session, e);
+            }
+        });
+    }
+
+    /// Stops the session's condensation, so a session being cleared does not
+    /// get the old summary written back.
+    pub fn abort(&self, session_id: &str) {
+        self.tasks.abort(session_id);
+    }
+
+    async fn condense(
+        &self,
+        memory: &dyn Memory,
+        session_id: &str,
+        summary: SessionSummary,
+        to_fold: &[StoredMessage],
+    ) -> Result<()> {
+        let mut transcript = String::new();
+        if !summary.content.is_empty() {
+            let _ = writeln!(transcript, "Existing summary:\n{}\n", summary.content);
+        }
+        transcript.push_str("New messages:\n");
+        for message in to_fold
+            .iter()
+            .map(|stored| &stored.message)
+            .filter(|m| !m.content.is_empty())
+        {
+            let _ = writeln!(transcript, "{}: {}", message.role, message.content);
+        }
+
+        let prompt = [
+            Message::system(SUMMARY_INSTRUCTIONS),
+            Message::user(&transcript, None),
+        ];
+        let options = GenerationOptions {
+            temperature: Some(SUMMARY_TEMPERATURE),
+            ..GenerationOptions::default()
+        };
+        let content = self.llm.chat(&prompt, &options).await?;
+
+        let updated = SessionSummary {
+            content: content.trim().to_string(),
+            through: to_fold.last().map(|stored| stored.id).or(summary.through),
+        };
+        memory
+            .set_metadata(session_id, SUMMARY_KEY, &serde_json::to_string(&updated)?)
+            .await?;
+
+        tracing::debug!(
+            "Summarized {} messages of session {}",
+            to_fold.len(),
+            session_id
+        );
+        Ok(())
+    }
+}
+
+async fn load(memory: &dyn Memory, session_id: &str) -> Result<SessionSummary> {
+    match memory.get_metadata(session_id, SUMMARY_KEY).await? {
+        Some(raw) => serde_json::from_str(&raw).context("Corrupt session summary"),
+        None => Ok(SessionSummary::default()),
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::task::AbortHandle;
+
+/// Background tasks writing to a session (its summary or persona state), so
+/// they can be stopped before the session is cleared instead of writing the
+/// old conversation back into the new one.
+#[derive(Default)]
+pub struct SessionTasks {
+    running: Arc<Mutex<HashMap<String, Vec<AbortHandle>>>>,
+}
+
+impl SessionTasks {
+    /// Spawns `task` for the session. With `exclusive`, nothing is spawned
+    /// while another task of the session runs; returns whether it was spawned.
+    pub fn spawn<F>(&self, session_id: &str, exclusive: bool, task: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut running = self.running.lock().expect("session tasks lock poisoned");
+        let handles = running.entry(session_id.to_string()).or_default();
+        // Tasks that panicked never removed themselves
+        handles.retain(|handle| !handle.is_finished());
+        if exclusive && !handles.is_empty() {
+            return false;
+        }
+
+        let registry = self.running.clone();
+        let session_id = session_id.to_string();
+        let handle = tokio::spawn(async move {
+            task.await;
+            // Registered before the lock was released, so it is there to remove
+            let id = tokio::task::id();
+            let mut running = registry.lock().expect("session tasks lock poisoned");
+            if let Some(handles) = running.get_mut(&session_id) {
+                handles.retain(|handle| handle.id() != id);
+                if handles.is_empty() {
+                    running.remove(&session_id);
+                }
+            }
+        });
+        handles.push(handle.abort_handle());
+        true
+    }
+
+    /// Stops every task of the session at its next await point.
+    pub fn abort(&self, session_id: &str) {
+        let handles = self
+            .running
+            .lock()
+            .expect("session tasks lock poisoned")
+            .remove(session_id);
+        for handle in handles.into_iter().flatten() {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn exclusive_tasks_wait_for_the_running_one() {
+        let tasks = SessionTasks::default();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        assert!(tasks.spawn("a", true, async {
+            let _ = rx.await;
+        }));
+        assert!(!tasks.spawn("a", true, async {}));
+        assert!(tasks.spawn("b", true, async {}));
+
+        tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(tasks.spawn("a", true, async {}));
+    }
+
+    #[tokio::test]
+    async fn abort_stops_the_session_tasks() {
+        let tasks = SessionTasks::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for session_id in ["a", "b"] {
+            let tx = tx.clone();
+            tasks.spawn(session_id, false, async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let _ = tx.send(session_id);
+            });
+        }
+        drop(tx);
+
+        tasks.abort("a");
+        assert_eq!(rx.recv().await, Some("b"));
+        assert_eq!(rx.recv().await, None);
+        assert!(tasks.spawn("a", true, async {}));
+    }
+}
@@ -0,0 +1,323 @@
+//! Importer for Character Card V2 files (`chara_card_v2`), either as JSON
+//! or embedded in a PNG as a base64 `chara` text chunk.
+
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+
+use crate::persona::{Example, LoreEntry, Lorebook};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const SPEC_V2: &str = "chara_card_v2";
+
+/// Card placeholders and the persona template variables they map to.
+const PLACEHOLDERS: [(&str, &str); 6] = [
+    ("{{char}}", "{{persona.name}}"),
+    ("{{Char}}", "{{persona.name}}"),
+    ("<BOT>", "{{persona.name}}"),
+    ("{{user}}", "{{user_name}}"),
+    ("{{User}}", "{{user_name}}"),
+    ("<USER>", "{{user_name}}"),
+];
+
+#[derive(Debug, Deserialize)]
+struct Card {
+    spec: String,
+    data: CardData,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CardData {
+    pub name: String,
+    pub description: String,
+    pub personality: String,
+    pub scenario: String,
+    pub first_mes: String,
+    pub mes_example: String,
+    pub creator_notes: String,
+    pub system_prompt: String,
+    pub post_history_instructions: String,
+    pub character_book: Option<CharacterBook>,
+}
+
+/// The card's embedded lorebook.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CharacterBook {
+    scan_depth: Option<usize>,
+    token_budget: Option<usize>,
+    recursive_scanning: Option<bool>,
+    entries: Vec<BookEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BookEntry {
+    #[serde(default)]
+    keys: Vec<String>,
+    #[serde(default)]
+    content: String,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    #[serde(default)]
+    insertion_order: i32,
+    priority: Option<i32>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// Recursion rounds for books with `recursive_scanning`.
+const RECURSION_DEPTH: usize = 2;
+
+/// Whether a JSON document is a character card rather than a persona.
+pub fn is_card(value: &serde_json::Value) -> bool {
+    value.get("spec").is_some() && value.get("data").is_some()
+}
+
+/// Whether a PNG image carries a character card; other images (such as
+/// avatars kept next to the personas) are not personas.
+pub fn is_png_card(bytes: &[u8]) -> bool {
+    matches!(png_text_chunk(bytes, "chara"), Ok(Some(_)))
+}
+
+pub fn from_json(value: serde_json::Value) -> Result<CardData> {
+    let card: Card = serde_json::from_value(value).context("Invalid character card")?;
+    if card.spec != SPEC_V2 {
+        anyhow::bail!(
+            "Unsupported character card spec '{}' (expected {SPEC_V2})",
+            card.spec
+        );
+    }
+    Ok(card.data)
+}
+
+/// Reads the card from the `chara` text chunk of a PNG image.
+pub fn from_png(bytes: &[u8]) -> Result<CardData> {
+    let encoded = png_text_chunk(bytes, "chara")?
+        .context("PNG has no embedded character card (no `chara` text chunk)")?;
+    let json = STANDARD
+        .decode(encoded.trim())
+        .context("Character card in PNG is not valid base64")?;
+    from_json(serde_json::from_slice(&json).context("Character card in PNG is not valid JSON")?)
+}
+
+/// Text of the first `tEXt` chunk with the given keyword.
+fn png_text_chunk(bytes: &[u8], keyword: &str) -> Result<Option<String>> {
+    let mut rest = bytes
+        .strip_prefix(PNG_SIGNATURE)
+        .context("Not a PNG image")?;
+
+    // Chunk: 4-byte length, 4-byte type, data, 4-byte CRC
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + len).context("PNG image is truncated")?;
+
+        if kind == b"tEXt"
+            && let Some((name, text)) = data.split_at_checked(keyword.len())
+            && name == keyword.as_bytes()
+            && text.first() == Some(&0)
+        {
+            // tEXt is Latin-1, which base64 text is a subset of
+            return Ok(Some(String::from_utf8_lossy(&text[1..]).into_owned()));
+        }
+        if kind == b"IEND" {
+            break;
+        }
+        rest = &rest[(12 + len).min(rest.len())..];
+    }
+    Ok(None)
+}
+
+impl CardData {
+    /// System prompt assembled from the card's character fields.
+    pub fn system_prompt(&self) -> String {
+        let mut sections = Vec::new();
+        // `{{original}}` stands for the frontend's default prompt; there is none here
+        sections.push(self.system_prompt.replace("{{original}}", ""));
+        sections.push(self.description.clone());
+        if !self.personality.trim().is_empty() {
+            sections.push(format!("{{{{char}}}}'s personality: {}", self.personality));
+        }
+        if !self.scenario.trim().is_empty() {
+            sections.push(format!("Scenario: {}", self.scenario));
+        }
+        sections.push(self.post_history_instructions.clone());
+
+        sections.retain(|section| !section.trim().is_empty());
+        convert_placeholders(sections.join("\n\n").trim())
+    }
+
+    pub fn greeting(&self) -> Option<String> {
+        Some(convert_placeholders(&self.first_mes)).filter(|greeting| !greeting.trim().is_empty())
+    }
+
+    /// First line of the creator's notes, shown in persona listings.
+    pub fn summary(&self) -> String {
+        self.creator_notes
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    pub fn lorebook(&self) -> Option<Lorebook> {
+        let book = self.character_book.as_ref()?;
+        let defaults = Lorebook::default();
+        Some(Lorebook {
+            scan_depth: book.scan_depth.unwrap_or(defaults.scan_depth),
+            token_budget: book.token_budget.unwrap_or(defaults.token_budget),
+            recursion_depth: if book.recursive_scanning.unwrap_or(false) {
+                RECURSION_DEPTH
+            } else {
+                0
+            },
+            entries: book
+                .entries
+                .iter()
+                .filter(|entry| entry.enabled && !entry.content.trim().is_empty())
+                .filter(|entry| entry.keys.iter().any(|key| !key.trim().is_empty()))
+                .map(|entry| LoreEntry {
+                    keys: entry.keys.clone(),
+                    content: convert_placeholders(&entry.content),
+                    // Either way, higher values are kept when the budget is short
+                    priority: entry.priority.unwrap_or(entry.insertion_order),
+                    max_tokens: None,
+                })
+                .collect(),
+        })
+    }
+
+    /// Exchanges from `mes_example`: `<START>`-separated blocks of
+    /// `{{user}}: ...` and `{{char}}: ...` lines.
+    pub fn examples(&self) -> Vec<Example> {
+        let text = convert_placeholders(&self.mes_example);
+        let mut examples = Vec::new();
+
+        for block in text.split("<START>") {
+            let mut user: Option<String> = None;
+            let mut current: Option<(bool, String)> = None;
+            let mut turns = Vec::new();
+
+            for line in block.lines() {
+                if let Some(text) = line.trim_start().strip_prefix("{{user_name}}:") {
+                    turns.extend(current.replace((true, text.trim().to_string())));
+                } else if let Some(text) = line.trim_start().strip_prefix("{{persona.name}}:") {
+                    turns.extend(current.replace((false, text.trim().to_string())));
+                } else if let Some((_, text)) = &mut current {
+                    // Continuation of a multi-line turn
+                    text.push('\n');
+                    text.push_str(line);
+                }
+            }
+            turns.extend(current);
+
+            for (is_user, text) in turns {
+                let text = text.trim().to_string();
+                if is_user {
+                    user = Some(text);
+                } else if let Some(user) = user.take().filter(|user| !user.is_empty())
+                    && !text.is_empty()
+                {
+                    examples.push(Example {
+                        user,
+                        assistant: text,
+                    });
+                }
+            }
+        }
+        examples
+    }
+}
+
+fn convert_placeholders(text: &str) -> String {
+    PLACEHOLDERS
+        .iter()
+        .fold(text.to_string(), |text, (card, persona)| {
+            text.replace(card, persona)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunk with a zero CRC; the reader does not check it.
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = u32::try_from(data.len()).unwrap().to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for chunk in chunks {
+            png.extend_from_slice(chunk);
+        }
+        png
+    }
+
+    #[test]
+    fn finds_text_chunk_by_keyword() {
+        let image = png(&[
+            chunk(b"IHDR", &[0; 13]),
+            chunk(b"tEXt", b"Comment\0hello"),
+            chunk(b"tEXt", b"chara\0abc"),
+            chunk(b"IEND", b""),
+        ]);
+        assert_eq!(
+            png_text_chunk(&image, "chara").unwrap().as_deref(),
+            Some("abc")
+        );
+        // A keyword that only prefixes another does not match
+        assert_eq!(png_text_chunk(&image, "Comm").unwrap(), None);
+    }
+
+    #[test]
+    fn stops_at_iend_and_rejects_bad_images() {
+        let image = png(&[chunk(b"IEND", b""), chunk(b"tEXt", b"chara\0abc")]);
+        assert_eq!(png_text_chunk(&image, "chara").unwrap(), None);
+
+        assert!(png_text_chunk(b"GIF89a", "chara").is_err());
+        let mut truncated = png(&[chunk(b"tEXt", b"chara\0abcdef")]);
+        truncated.truncate(truncated.len() - 8);
+        assert!(png_text_chunk(&truncated, "chara").is_err());
+    }
+
+    #[test]
+    fn reads_card_from_png() {
+        let card = r#"{"spec":"chara_card_v2","data":{"name":"Mia","first_mes":"Hi {{user}}"}}"#;
+        let text = [b"chara\0".as_slice(), STANDARD.encode(card).as_bytes()].concat();
+        let data = from_png(&png(&[chunk(b"tEXt", &text)])).unwrap();
+        assert_eq!(data.name, "Mia");
+        assert_eq!(data.greeting().as_deref(), Some("Hi {{user_name}}"));
+    }
+
+    #[test]
+    fn splits_examples_into_exchanges() {
+        let card = CardData {
+            mes_example: "<START>\n{{user}}: Hi\n{{char}}: Hello,\nhow are you?\n\
+                          <START>\n<USER>: Bye\n{{char}}: See you\n{{user}}: Unanswered\n\
+                          <START>\n{{char}}: Unprompted"
+                .to_string(),
+            ..CardData::default()
+        };
+        let examples: Vec<(String, String)> = card
+            .examples()
+            .into_iter()
+            .map(|example| (example.user, example.assistant))
+            .collect();
+        assert_eq!(
+            examples,
+            [
+                ("Hi".to_string(), "Hello,\nhow are you?".to_string()),
+                ("Bye".to_string(), "See you".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::persona::{Example, Lorebook, Persona, StateConfig, card};
+
+/// Extensions of files loaded as personas.
+const EXTENSIONS: [&str; 5] = ["json", "toml", "yaml", "yml", "png"];
+
+/// A persona file as written, before `extends` and `includes` are resolved.
+/// Unknown fields are rejected so that typos do not go unnoticed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PersonaFile {
+    /// Persona (by file name) whose fields this one starts from.
+    extends: Option<String>,
+    /// Prompt fragments, relative to the avatars directory, placed between
+    /// the inherited prompt and this persona's own.
+    #[serde(default)]
+    includes: Vec<String>,
+    name: Option<String>,
+    description: Option<String>,
+    system_prompt: Option<String>,
+    greeting: Option<String>,
+    examples: Option<Vec<Example>>,
+    lorebook: Option<Lorebook>,
+    state: Option<StateConfig>,
+    provider: Option<String>,
+    model: Option<String>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    stop: Option<Vec<String>>,
+    seed: Option<u64>,
+}
+
+/// Loads every persona in `dir`, keyed and sorted by file name. Each persona
+/// succeeds or fails on its own, so one broken file does not hide the rest.
+pub(super) fn load_dir(dir: &Path) -> Result<Vec<(String, Result<Persona>)>> {
+    let mut files: BTreeMap<String, (PathBuf, Result<PersonaFile>)> = BTreeMap::new();
+    for path in persona_files(dir)? {
+        let key = file_key(&path);
+        let file = match files.get(&key) {
+            // `alice.json` next to `alice.toml` would make the name ambiguous
+            Some((other, _)) => Err(anyhow::anyhow!(
+                "Persona files {} and {} are both named '{}'",
+                other.display(),
+                path.display(),
+                key
+            )),
+            None => read_file(&path),
+        };
+        files.insert(key, (path, file));
+    }
+
+    let mut resolver = Resolver {
+        dir,
+        files: &files,
+        resolved: HashMap::new(),
+    };
+    let mut personas: Vec<(String, Result<Persona>)> = files
+        .keys()
+        .map(|key| (key.clone(), resolver.resolve(key, &mut Vec::new())))
+        .collect();
+
+    // Personas sharing a name cannot be told apart in listings
+    let mut names: HashMap<String, &Path> = HashMap::new();
+    for (key, persona) in &mut personas {
+        let Ok(loaded) = persona else {
+            continue;
+        };
+        let path = &files[key].0;
+        if let Some(other) = names.get(&loaded.name) {
+            *persona = Err(anyhow::anyhow!(
+                "Persona files {} and {} both use the name '{}'",
+                other.display(),
+                path.display(),
+                loaded.name
+            ));
+        } else {
+            names.insert(loaded.name.clone(), path);
+        }
+    }
+    Ok(personas)
+}
+
+fn persona_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).context(format!(
+        "Failed to read avatars directory: {}",
+        dir.display()
+    ))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(ext) = path.extension().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !EXTENSIONS.contains(&ext) {
+            continue;
+        }
+        // Unreadable files are still reported when they are loaded
+        if ext == "png" && fs::read(&path).is_ok_and(|bytes| !card::is_png_card(&bytes)) {
+            tracing::debug!("Skipping {}, which holds no character card", path.display());
+            continue;
+        }
+        files.push(path);
+    }
+    // Sorted, so that duplicate names are reported the same way every time
+    files.sort();
+    Ok(files)
+}
+
+/// Personas are selected (and extended) by file name.
+fn file_key(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_file(path: &Path) -> Result<PersonaFile> {
+    let content =
+        fs::read(path).context(format!("Failed to read persona file: {}", path.display()))?;
+    parse_file(path, &content).context(format!("Failed to parse persona file: {}", path.display()))
+}
+
+fn parse_file(path: &Path, content: &[u8]) -> Result<PersonaFile> {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("png") => Ok(from_card(&card::from_png(content)?)),
+        Some("toml") => Ok(toml::from_str(std::str::from_utf8(content)?)?),
+        Some("yaml" | "yml") => Ok(serde_yaml_ng::from_slice(content)?),
+        _ => {
+            let value: serde_json::Value = serde_json::from_slice(content)?;
+            if card::is_card(&value) {
+                Ok(from_card(&card::from_json(value)?))
+            } else {
+                Ok(serde_json::from_value(value)?)
+            }
+        }
+    }
+}
+
+fn from_card(card: &card::CardData) -> PersonaFile {
+    PersonaFile {
+        name: Some(card.name.clone()).filter(|name| !name.trim().is_empty()),
+        description: Some(card.summary()),
+        system_prompt: Some(card.system_prompt()),
+        greeting: card.greeting(),
+        examples: Some(card.examples()),
+        lorebook: card.lorebook(),
+        ..PersonaFile::default()
+    }
+}
+
+impl PersonaFile {
+    /// Rejects examples and lorebook entries that would add nothing, and
+    /// impossible state settings.
+    fn check_entries(&self, path: &Path) -> Result<()> {
+        if let Some(examples) = &self.examples
+            && let Some(index) = examples.iter().position(|example| {
+                example.user.trim().is_empty() || example.assistant.trim().is_empty()
+            })
+        {
+            anyhow::bail!(
+                "Persona file {} has an empty message in examples[{}]",
+                path.display(),
+                index
+            );
+        }
+        if let Some(lorebook) = &self.lorebook
+            && let Some(index) = lorebook.entries.iter().position(|entry| {
+                entry.content.trim().is_empty()
+                    || entry.keys.iter().all(|key| key.trim().is_empty())
+            })
+        {
+            anyhow::bail!(
+                "Persona file {} has a lorebook entry without keys or content at entries[{}]",
+                path.display(),
+                index
+            );
+        }
+        if let Some(state) = &self.state
+            && state.min_affection > state.max_affection
+        {
+            anyhow::bail!(
+                "Persona file {} has state.min_affection above state.max_affection",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+struct Resolver<'a> {
+    dir: &'a Path,
+    files: &'a BTreeMap<String, (PathBuf, Result<PersonaFile>)>,
+    resolved: HashMap<String, Persona>,
+}
+
+impl Resolver<'_> {
+    /// Builds the persona in file `key` on top of its resolved parent.
+    /// `chain` holds the personas being resolved below this one.
+    fn resolve(&mut self, key: &str, chain: &mut Vec<String>) -> Result<Persona> {
+        if let Some(persona) = self.resolved.get(key) {
+            return Ok(persona.clone());
+        }
+
+        let files = self.files;
+        let (path, file) = &files[key];
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => anyhow::bail!("{e:#}"),
+        };
+
+        let parent = match &file.extends {
+            Some(parent) if let Some(start) = chain.iter().position(|k| k == parent) => {
+                let cycle = chain[start..].join(" -> ");
+                anyhow::bail!(
+                    "Persona file {} extends '{}', which forms a cycle: {} -> {} -> {}",
+                    path.display(),
+                    parent,
+                    cycle,
+                    key,
+                    parent
+                );
+            }
+            Some(parent) if parent == key => {
+                anyhow::bail!("Persona file {} extends itself", path.display());
+            }
+            Some(parent) if !files.contains_key(parent) => {
+                anyhow::bail!(
+                    "Persona file {} extends unknown persona '{}'",
+                    path.display(),
+                    parent
+                );
+            }
+            Some(parent) => {
+                chain.push(key.to_string());
+                let resolved = self.resolve(parent, chain);
+                chain.pop();
+                Some(resolved.with_context(|| {
+                    format!("Persona file {} extends '{}'", path.display(), parent)
+                })?)
+            }
+            None => None,
+        };
+
+        // Prompts compose: inherited prompt, then fragments, then our own
+        let mut sections = Vec::new();
+        if let Some(parent) = &parent {
+            sections.push(parent.system_prompt.clone());
+        }
+        for include in &file.includes {
+            let fragment_path = self.dir.join(include);
+            let fragment = fs::read_to_string(&fragment_path).with_context(|| {
+                format!(
+                    "Persona file {} includes {}, which could not be read",
+                    path.display(),
+                    fragment_path.display()
+                )
+            })?;
+            sections.push(fragment.trim().to_string());
+        }
+        sections.extend(file.system_prompt.clone());
+        sections.retain(|section| !section.trim().is_empty());
+        if sections.is_empty() {
+            anyhow::bail!("Persona file {} has an empty system_prompt", path.display());
+        }
+        file.check_entries(path)?;
+
+        // Every other field falls back to the parent's, except those describing
+        // the persona itself
+        let base = parent.unwrap_or_default();
+        let persona = Persona {
+            name: file.name.clone().unwrap_or_else(|| key.to_string()),
+            description: file.description.clone().unwrap_or_default(),
+            system_prompt: sections.join("\n\n"),
+            greeting: file.greeting.clone().or(base.greeting),
+            examples: file.examples.clone().unwrap_or(base.examples),
+            lorebook: file.lorebook.clone().or(base.lorebook),
+            state: file.state.clone().or(base.state),
+            provider: file.provider.clone().or(base.provider),
+            model: file.model.clone().or(base.model),
+            temperature: file.temperature.or(base.temperature),
+            top_p: file.top_p.or(base.top_p),
+            max_tokens: file.max_tokens.or(base.max_tokens),
+            presence_penalty: file.presence_penalty.or(base.presence_penalty),
+            frequency_penalty: file.frequency_penalty.or(base.frequency_penalty),
+            stop: file.stop.clone().unwrap_or(base.stop),
+            seed: file.seed.or(base.seed),
+        };
+
+        self.resolved.insert(key.to_string(), persona.clone());
+        Ok(persona)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads personas from `files`, written as `(file name, content)`.
+    fn load(files: &[(&str, &str)]) -> BTreeMap<String, Result<Persona>> {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        load_dir(dir.path()).unwrap().into_iter().collect()
+    }
+
+    fn error(personas: &BTreeMap<String, Result<Persona>>, key: &str) -> String {
+        format!("{:#}", personas[key].as_ref().unwrap_err())
+    }
+
+    #[test]
+    fn extends_inherit_and_compose_prompts() {
+        let personas = load(&[
+            (
+                "base.toml",
+                "system_prompt = \"Be kind.\"\ntemperature = 0.5",
+            ),
+            (
+                "child.toml",
+                "extends = \"base\"\nsystem_prompt = \"Be brief.\"",
+            ),
+        ]);
+        let child = personas["child"].as_ref().unwrap();
+        assert_eq!(child.name, "child");
+        assert_eq!(child.system_prompt, "Be kind.\n\nBe brief.");
+        assert_eq!(child.temperature, Some(0.5));
+    }
+
+    #[test]
+    fn skips_images_without_a_card() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("alice.json"), r#"{"system_prompt": "Hi"}"#).unwrap();
+        // An avatar: a PNG without a `chara` text chunk
+        let mut avatar = b"\x89PNG\r\n\x1a\n".to_vec();
+        avatar.extend_from_slice(&[0, 0, 0, 0]);
+        avatar.extend_from_slice(b"IEND");
+        avatar.extend_from_slice(&[0; 4]);
+        fs::write(dir.path().join("alice.png"), avatar).unwrap();
+        fs::write(dir.path().join("bob.png"), b"not an image").unwrap();
+
+        let personas = load_dir(dir.path()).unwrap();
+        assert_eq!(personas.len(), 1);
+        assert_eq!(personas[0].0, "alice");
+        assert!(personas[0].1.is_ok());
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let personas = load(&[
+            ("a.toml", "extends = \"b\"\nsystem_prompt = \"a\""),
+            ("b.toml", "extends = \"c\"\nsystem_prompt = \"b\""),
+            ("c.toml", "extends = \"a\"\nsystem_prompt = \"c\""),
+            ("d.toml", "extends = \"a\"\nsystem_prompt = \"d\""),
+        ]);
+        assert!(error(&personas, "a").contains("forms a cycle: a -> b -> c -> a"));
+        assert!(error(&personas, "b").contains("forms a cycle: b -> c -> a -> b"));
+        // Outside the cycle, but extends into it
+        assert!(error(&personas, "d").contains("forms a cycle"));
+    }
+
+    #[test]
+    fn rejects_self_and_unknown_parents() {
+        let personas = load(&[
+            ("me.toml", "extends = \"me\"\nsystem_prompt = \"x\""),
+            ("orphan.toml", "extends = \"nobody\"\nsystem_prompt = \"x\""),
+        ]);
+        assert!(error(&personas, "me").contains("extends itself"));
+        assert!(error(&personas, "orphan").contains("unknown persona 'nobody'"));
+    }
+
+    #[test]
+    fn parent_errors_stay_with_their_children() {
+        let personas = load(&[
+            ("broken.toml", "system_prompt = "),
+            ("child.toml", "extends = \"broken\"\nsystem_prompt = \"x\""),
+            ("other.toml", "system_prompt = \"fine\""),
+        ]);
+        assert!(personas["broken"].is_err());
+        assert!(error(&personas, "child").contains("extends 'broken'"));
+        assert!(personas["other"].is_ok());
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::{estimate_tokens, truncate_to_tokens},
+    prompt::Message,
+};
+
+/// World information injected into the context only while recent messages
+/// mention it, so that large settings cost nothing until they are relevant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lorebook {
+    /// Number of most recent messages searched for keywords.
+    #[serde(default = "default_scan_depth")]
+    pub scan_depth: usize,
+    /// Upper bound on the tokens spent on entries per reply.
+    #[serde(default = "default_token_budget")]
+    pub token_budget: usize,
+    /// Rounds in which the content of triggered entries may trigger further
+    /// entries; 0 disables recursion.
+    #[serde(default)]
+    pub recursion_depth: usize,
+    #[serde(default)]
+    pub entries: Vec<LoreEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoreEntry {
+    /// Keywords, matched case-insensitively anywhere in a message.
+    pub keys: Vec<String>,
+    pub content: String,
+    /// Entries with a higher priority are kept first when the budget is short.
+    #[serde(default)]
+    pub priority: i32,
+    /// Content beyond this many tokens is cut off.
+    pub max_tokens: Option<usize>,
+}
+
+fn default_scan_depth() -> usize {
+    4
+}
+
+fn default_token_budget() -> usize {
+    1024
+}
+
+impl Default for Lorebook {
+    fn default() -> Self {
+        Self {
+            scan_depth: default_scan_depth(),
+            token_budget: default_token_budget(),
+            recursion_depth: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl LoreEntry {
+    fn matches(&self, text: &str) -> bool {
+        self.keys
+            .iter()
+            .filter(|key| !key.trim().is_empty())
+            .any(|key| text.contains(&key.to_lowercase()))
+    }
+}
+
+impl Lorebook {
+    /// Content of the entries triggered by the recent `history`, highest
+    /// priority first and within the token budget.
+    pub fn activate(&self, history: &[Message]) -> Vec<&str> {
+        let mut text = history
+            .iter()
+            .rev()
+            .take(self.scan_depth)
+            .map(|message| message.content.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut active = vec![false; self.entries.len()];
+        for _ in 0..=self.recursion_depth {
+            let triggered: Vec<usize> = (0..self.entries.len())
+                .filter(|&i| !active[i] && self.entries[i].matches(&text))
+                .collect();
+            if triggered.is_empty() {
+                break;
+            }
+            for &i in &triggered {
+                active[i] = true;
+            }
+            // The next round only looks at what was just added
+            text = triggered
+                .iter()
+                .map(|&i| self.entries[i].content.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+
+        let mut selected: Vec<&LoreEntry> = self
+            .entries
+            .iter()
+            .zip(active)
+            .filter_map(|(entry, active)| active.then_some(entry))
+            .collect();
+        selected.sort_by_key(|entry| std::cmp::Reverse(entry.priority));
+
+        let mut remaining = self.token_budget;
+        let mut contents = Vec::new();
+        for entry in selected {
+            let content = match entry.max_tokens {
+                Some(max_tokens) => truncate_to_tokens(&entry.content, max_tokens),
+                None => &entry.content,
+            };
+            let cost = estimate_tokens(content);
+            // A lower-priority entry may still fit where this one did not
+            if cost > remaining {
+                continue;
+            }
+            remaining -= cost;
+            contents.push(content);
+        }
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(keys: &[&str], content: &str, priority: i32) -> LoreEntry {
+        LoreEntry {
+            keys: keys.iter().map(ToString::to_string).collect(),
+            content: content.to_string(),
+            priority,
+            max_tokens: None,
+        }
+    }
+
+    fn book(entries: Vec<LoreEntry>) -> Lorebook {
+        Lorebook {
+            entries,
+            ..Lorebook::default()
+        }
+    }
+
+    #[test]
+    fn matches_keys_in_recent_messages() {
+        let mut book = book(vec![
+            entry(&["Castle"], "castle lore", 0),
+            entry(&["dragon"], "dragon lore", 0),
+            entry(&["", " "], "never", 0),
+        ]);
+        book.scan_depth = 2;
+        let history = [
+            Message::user("A dragon appears", None),
+            Message::assistant("Run!"),
+            Message::user("To the CASTLE", None),
+        ];
+        // The dragon is three messages back, beyond the scan depth
+        assert_eq!(book.activate(&history), ["castle lore"]);
+        book.scan_depth = 3;
+        assert_eq!(book.activate(&history), ["castle lore", "dragon lore"]);
+    }
+
+    #[test]
+    fn recursion_follows_triggered_content() {
+        let mut book = book(vec![
+            entry(&["castle"], "The castle is guarded by a dragon.", 0),
+            entry(&["dragon"], "The dragon sleeps by day.", 0),
+            entry(&["sleeps"], "Sleeping dragons snore.", 0),
+        ]);
+        let history = [Message::user("Where is the castle?", None)];
+        assert_eq!(book.activate(&history).len(), 1);
+        book.recursion_depth = 1;
+        assert_eq!(book.activate(&history).len(), 2);
+        book.recursion_depth = 2;
+        assert_eq!(book.activate(&history).len(), 3);
+    }
+
+    #[test]
+    fn keeps_highest_priority_within_budget() {
+        let mut book = book(vec![
+            entry(&["a"], "low", 1),
+            entry(&["a"], &"x".repeat(40), 3),
+            entry(&["a"], "high", 2),
+        ]);
+        book.token_budget = 5;
+        let history = [Message::user("a", None)];
+        // The 10-token entry does not fit, but those after it still do
+        assert_eq!(book.activate(&history), ["high", "low"]);
+    }
+
+    #[test]
+    fn truncates_to_max_tokens() {
+        let mut long = entry(&["a"], "one two three four five", 0);
+        long.max_tokens = Some(2);
+        let book = book(vec![long]);
+        assert_eq!(book.activate(&[Message::user("a", None)]), ["one two "]);
+    }
+}
@@ -1,70 +1,228 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::llm::GenerationOptions;
+
+pub use self::{
+    lorebook::{LoreEntry, Lorebook},
+    state::StateConfig,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Persona {
     pub name: String,
     pub description: String,
     pub system_prompt: String,
     pub greeting: Option<String>,
+    /// Sample exchanges showing the persona's tone, sent before the history.
+    #[serde(default)]
+    pub examples: Vec<Example>,
+    /// World information added to the context when recent messages mention it.
+    pub lorebook: Option<Lorebook>,
+    /// Mood and affection tracked per session, exposed as `{{state.*}}`.
+    pub state: Option<StateConfig>,
+    /// Provider (as named in the `llm` config) to use instead of the default chain.
+    pub provider: Option<String>,
+    /// Model to request instead of the provider's configured one.
+    pub model: Option<String>,
+    // Default sampling parameters for replies in this persona
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    pub seed: Option<u64>,
+}
+
+/// One user/assistant exchange of a persona's few-shot examples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Example {
+    pub user: String,
+    pub assistant: String,
 }
 
+impl Persona {
+    /// Sampling parameters for replies in this persona.
+    pub fn generation_options(&self) -> GenerationOptions {
+        GenerationOptions {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            stop: self.stop.clone(),
+            seed: self.seed,
+        }
+    }
+}
+
+/// Personas by the name they are selected by.
+type PersonaMap = HashMap<String, Arc<Persona>>;
+
 pub struct PersonaManager {
-    personas: HashMap<String, Persona>,
+    dir: PathBuf,
     default_persona: String,
+    personas: RwLock<PersonaMap>,
 }
 
 impl PersonaManager {
+    /// Loads the avatars directory, failing with every problem found if any
+    /// persona is invalid or the default one is missing.
     pub fn new(avatars_dir: &str, default_name: &str) -> Result<Self> {
+        let dir = PathBuf::from(avatars_dir);
+        let (personas, problems) = load_checked(&dir, default_name)?;
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "Invalid personas in {}:\n  - {}",
+                dir.display(),
+                problems.join("\n  - ")
+            );
+        }
+
+        Ok(Self {
+            dir,
+            default_persona: default_name.to_string(),
+            personas: RwLock::new(personas),
+        })
+    }
+
+    /// Personas in `avatars_dir` that load, and every problem with the rest,
+    /// for linting without starting the bot.
+    pub fn check(avatars_dir: &str, default_name: &str) -> Result<(PersonaMap, Vec<String>)> {
+        load_checked(Path::new(avatars_dir), default_name)
+    }
+
+    /// Re-reads the avatars directory and swaps the result in at once. A
+    /// persona that fails to load keeps its previous version, if there was one.
+    pub fn reload(&self) -> Result<()> {
+        let previous = self.personas.read().expect("persona lock poisoned").clone();
         let mut personas = HashMap::new();
 
-        // Read all json files in avatars_dir
-        let entries = fs::read_dir(avatars_dir)
-            .context(format!("Failed to read avatars directory: {avatars_dir}"))?;
-
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                let content = fs::read_to_string(&path)
-                    .context(format!("Failed to read persona file: {}", path.display()))?;
-                let persona: Persona = serde_json::from_str(&content)
-                    .context(format!("Failed to parse persona file: {}", path.display()))?;
-
-                let key = path
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or(&persona.name) // Fallback to inner name if file stem fails? Or just use inner name? The prompt
-                    // says "use filename".
-                    .to_string();
-
-                personas.insert(key, persona);
+        for (key, persona) in loader::load_dir(&self.dir)? {
+            match persona {
+                Ok(persona) => {
+                    personas.insert(key, Arc::new(persona));
+                }
+                Err(e) => {
+                    tracing::error!("{:#}; keeping the previous version", e);
+                    if let Some(persona) = previous.get(&key) {
+                        personas.insert(key, persona.clone());
+                    }
+                }
             }
         }
 
         if personas.is_empty() {
-            // If no files, create a fallback in memory
-            let fallback = Persona {
-                name: "default".to_string(),
-                description: "Default AI Assistant".to_string(),
-                system_prompt: "You are a helpful AI assistant.".to_string(),
-                greeting: Some("Hello! How can I help you?".to_string()),
-            };
-            personas.insert("default".to_string(), fallback);
+            personas.insert("default".to_string(), Arc::new(fallback_persona()));
+        }
+        if !personas.contains_key(&self.default_persona)
+            && let Some(default) = previous.get(&self.default_persona)
+        {
+            tracing::error!(
+                "Default persona '{}' is gone from {}; keeping the previous version",
+                self.default_persona,
+                self.dir.display()
+            );
+            personas.insert(self.default_persona.clone(), default.clone());
         }
 
-        Ok(Self {
-            personas,
-            default_persona: default_name.to_string(),
-        })
+        tracing::info!(
+            "Reloaded {} personas from {}",
+            personas.len(),
+            self.dir.display()
+        );
+        *self.personas.write().expect("persona lock poisoned") = personas;
+        Ok(())
     }
 
-    pub fn get_default_persona(&self) -> &Persona {
+    /// Persona loaded from `<name>.json` (or `.toml`, `.yaml`, `.png`, ...).
+    pub fn get(&self, name: &str) -> Option<Arc<Persona>> {
         self.personas
-            .get(&self.default_persona)
-            .or_else(|| self.personas.values().next())
-            .expect("PersonaManager should have at least one persona")
+            .read()
+            .expect("persona lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// All personas with the name they are selected by, sorted by name.
+    pub fn list(&self) -> Vec<(String, Arc<Persona>)> {
+        let mut personas: Vec<(String, Arc<Persona>)> = self
+            .personas
+            .read()
+            .expect("persona lock poisoned")
+            .iter()
+            .map(|(name, persona)| (name.clone(), persona.clone()))
+            .collect();
+        personas.sort_by(|(a, _), (b, _)| a.cmp(b));
+        personas
+    }
+
+    /// Name of the persona returned by [`PersonaManager::get_default_persona`].
+    pub fn default_name(&self) -> String {
+        self.default_persona.clone()
+    }
+
+    pub fn get_default_persona(&self) -> Arc<Persona> {
+        self.get(&self.default_persona)
+            .expect("the default persona is checked at load and kept on reload")
     }
 }
+
+/// Personas in `dir` that loaded, and the problems with the rest.
+fn load_checked(dir: &Path, default_name: &str) -> Result<(PersonaMap, Vec<String>)> {
+    let mut personas = HashMap::new();
+    let mut problems = Vec::new();
+    let mut found = false;
+
+    // Read all persona files and character cards in avatars_dir, resolving
+    // `extends` and `includes`
+    for (key, persona) in loader::load_dir(dir)? {
+        found |= key == default_name;
+        match persona {
+            Ok(persona) => {
+                personas.insert(key, Arc::new(persona));
+            }
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    }
+
+    if personas.is_empty() && problems.is_empty() {
+        // If no files, create a fallback in memory
+        personas.insert("default".to_string(), Arc::new(fallback_persona()));
+        found = default_name == "default";
+    }
+    if !found {
+        problems.push(format!(
+            "Default persona '{default_name}' not found in {} (DEFAULT_PERSONA / persona.default)",
+            dir.display()
+        ));
+    }
+    Ok((personas, problems))
+}
+
+fn fallback_persona() -> Persona {
+    Persona {
+        name: "default".to_string(),
+        description: "Default AI Assistant".to_string(),
+        system_prompt: "You are a helpful AI assistant.".to_string(),
+        greeting: Some("Hello! How can I help you?".to_string()),
+        ..Persona::default()
+    }
+}
+
+mod card;
+mod loader;
+pub mod lorebook;
+pub mod state;
+pub mod template;
+pub mod watcher;
@@ -0,0 +1,590 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    llm::{GenerationOptions, LLMClient},
+    memory::{Memory, tasks::SessionTasks},
+    prompt::Message,
+};
+
+/// Prefix of the session metadata key holding a persona's state; the persona
+/// name follows it.
+const STATE_KEY_PREFIX: &str = "state:";
+
+/// Largest affection change a single LLM-extracted delta may apply.
+const MAX_LLM_AFFECTION_STEP: i32 = 5;
+
+const DELTA_INSTRUCTIONS: &str = "You track how a role-play character feels about the user. \
+Given the character's current state and the latest exchange, reply with only a JSON object \
+describing the change: {\"mood\": new mood or null, \"affection\": change between -5 and 5, \
+\"custom\": {key: new value} for custom values that changed}.";
+
+// The delta should follow the exchange, not the persona's creativity.
+const DELTA_TEMPERATURE: f32 = 0.2;
+
+/// How a persona's state evolves, from the `state` section of a persona file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+    #[serde(default = "default_mood")]
+    pub initial_mood: String,
+    #[serde(default)]
+    pub initial_affection: i32,
+    #[serde(default = "default_min_affection")]
+    pub min_affection: i32,
+    #[serde(default = "default_max_affection")]
+    pub max_affection: i32,
+    /// Moods the state may take, offered to the LLM; any mood when empty.
+    #[serde(default)]
+    pub moods: Vec<String>,
+    /// Text added to the system prompt while the persona is in a mood.
+    #[serde(default)]
+    pub mood_prompts: BTreeMap<String, String>,
+    #[serde(default)]
+    pub update: StateUpdate,
+    /// Keyword rules applied to each user message with `update = "rules"`.
+    #[serde(default)]
+    pub rules: Vec<StateRule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateUpdate {
+    /// Apply the matching [`StateRule`]s.
+    #[default]
+    Rules,
+    /// Ask the LLM for a JSON delta after each turn.
+    Llm,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateRule {
+    /// Keywords, matched case-insensitively in the user's message.
+    pub keywords: Vec<String>,
+    /// Added to the affection score.
+    #[serde(default)]
+    pub affection: i32,
+    /// Mood to switch to.
+    pub mood: Option<String>,
+    /// Custom values to set.
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+}
+
+fn default_mood() -> String {
+    "neutral".to_string()
+}
+
+fn default_min_affection() -> i32 {
+    -100
+}
+
+fn default_max_affection() -> i32 {
+    100
+}
+
+/// Relationship state of one persona in one session.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersonaState {
+    pub mood: String,
+    pub affection: i32,
+    #[serde(default)]
+    pub custom: BTreeMap<String, String>,
+}
+
+/// Change to a [`PersonaState`], as produced by a rule or by the LLM.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StateDelta {
+    mood: Option<String>,
+    affection: i32,
+    custom: BTreeMap<String, serde_json::Value>,
+}
+
+impl StateConfig {
+    pub fn initial_state(&self) -> PersonaState {
+        PersonaState {
+            mood: self.initial_mood.clone(),
+            affection: self
+                .initial_affection
+                .clamp(self.min_affection, self.max_affection),
+            custom: BTreeMap::new(),
+        }
+    }
+
+    /// Extra system prompt text for the state's mood.
+    pub fn mood_prompt(&self, state: &PersonaState) -> Option<&str> {
+        self.mood_prompts.get(&state.mood).map(String::as_str)
+    }
+
+    fn apply(&self, state: &mut PersonaState, delta: StateDelta) {
+        if let Some(mood) = delta.mood.filter(|mood| !mood.trim().is_empty()) {
+            if self.moods.is_empty() || self.moods.contains(&mood) {
+                state.mood = mood;
+            } else {
+                tracing::debug!("Ignoring unknown mood '{}'", mood);
+            }
+        }
+        state.affection = state
+            .affection
+            .saturating_add(delta.affection)
+            .clamp(self.min_affection, self.max_affection);
+        for (key, value) in delta.custom {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                other => other.to_string(),
+            };
+            state.custom.insert(key, value);
+        }
+    }
+
+    /// Combined effect of the rules whose keywords appear in `input`.
+    fn rules_delta(&self, input: &str) -> Option<StateDelta> {
+        let input = input.to_lowercase();
+        let mut delta: Option<StateDelta> = None;
+        for rule in &self.rules {
+            if !rule
+                .keywords
+                .iter()
+                .filter(|keyword| !keyword.trim().is_empty())
+                .any(|keyword| input.contains(&keyword.to_lowercase()))
+            {
+                continue;
+            }
+            let delta = delta.get_or_insert_default();
+            delta.affection = delta.affection.saturating_add(rule.affection);
+            if rule.mood.is_some() {
+                delta.mood.clone_from(&rule.mood);
+            }
+            for (key, value) in &rule.set {
+                delta
+                    .custom
+                    .insert(key.clone(), serde_json::Value::String(value.clone()));
+            }
+        }
+        delta
+    }
+}
+
+/// Session id and persona name.
+type StateKey = (String, String);
+
+/// Loads, stores and updates persona states, in the session metadata when
+/// there is a memory and in process otherwise.
+pub struct StateTracker {
+    memory: Option<Arc<dyn Memory>>,
+    local: Mutex<HashMap<StateKey, PersonaState>>,
+    /// Background LLM updates.
+    tasks: SessionTasks,
+    /// One update at a time per (session, persona), so that none is lost to
+    /// another that loaded the same state.
+    updating: Mutex<HashMap<StateKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// What a finished turn looked like, for updating the state.
+pub struct Exchange {
+    pub persona_name: String,
+    pub config: StateConfig,
+    pub llm: Arc<dyn LLMClient>,
+    pub input: String,
+    pub reply: String,
+}
+
+impl StateTracker {
+    pub fn new(memory: Option<Arc<dyn Memory>>) -> Self {
+        Self {
+            memory,
+            local: Mutex::new(HashMap::new()),
+            tasks: SessionTasks::default(),
+            updating: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stops the session's background updates, so a session being cleared
+    /// does not get the old state written back.
+    pub fn abort(&self, session_id: &str) {
+        self.tasks.abort(session_id);
+    }
+
+    /// Drops the session's in-process states; states kept in the session
+    /// metadata go with the session.
+    pub fn clear_local(&self, session_id: &str) {
+        self.local
+            .lock()
+            .expect("persona state lock poisoned")
+            .retain(|(session, _), _| session != session_id);
+    }
+
+    /// State of `persona_name` in the session, or the initial one.
+    pub async fn load(
+        &self,
+        session_id: &str,
+        persona_name: &str,
+        config: &StateConfig,
+    ) -> Result<PersonaState> {
+        let stored = if let Some(mem) = &self.memory {
+            match mem
+                .get_metadata(session_id, &state_key(persona_name))
+                .await?
+            {
+                Some(raw) => Some(serde_json::from_str(&raw).context("Corrupt persona state")?),
+                None => None,
+            }
+        } else {
+            self.local
+                .lock()
+                .expect("persona state lock poisoned")
+                .get(&(session_id.to_string(), persona_name.to_string()))
+                .cloned()
+        };
+        Ok(stored.unwrap_or_else(|| config.initial_state()))
+    }
+
+    async fn save(&self, session_id: &str, persona_name: &str, state: PersonaState) -> Result<()> {
+        if let Some(mem) = &self.memory {
+            mem.set_metadata(
+                session_id,
+                &state_key(persona_name),
+                &serde_json::to_string(&state)?,
+            )
+            .await?;
+        } else {
+            self.local
+                .lock()
+                .expect("persona state lock poisoned")
+                .insert((session_id.to_string(), persona_name.to_string()), state);
+        }
+        Ok(())
+    }
+
+    /// Applies the effect of a finished turn. Rules apply at once; the LLM
+    /// delta is extracted in the background so the reply is not delayed.
+    pub async fn update(self: &Arc<Self>, session_id: &str, exchange: Exchange) -> Result<()> {
+        match exchange.config.update {
+            StateUpdate::Rules => {
+                let Some(delta) = exchange.config.rules_delta(&exchange.input) else {
+                    return Ok(());
+                };
+                self.serialized(session_id, &exchange.persona_name, async {
+                    let mut state = self
+                        .load(session_id, &exchange.persona_name, &exchange.config)
+                        .await?;
+                    exchange.config.apply(&mut state, delta);
+                    self.save(session_id, &exchange.persona_name, state).await
+                })
+                .await
+            }
+            StateUpdate::Llm => {
+                let this = self.clone();
+                let task_session = session_id.to_string();
+                self.tasks.spawn(session_id, false, async move {
+                    let update = this.update_with_llm(&task_session, &exchange);
+                    if let Err(e) = this
+                        .serialized(&task_session, &exchange.persona_name, update)
+                        .await
+                    {
+                        tracing::error!(
+                            "Failed to update the state of persona {} in session {}: {:#}",
+                            exchange.persona_name,
+                            task_session,
+                            e
+                        );
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs `update` once no other update of the persona's state in the
+    /// session is running.
+    async fn serialized<T>(
+        &self,
+        session_id: &str,
+        persona_name: &str,
+        update: impl Future<Output = T>,
+    ) -> T {
+        let key = (session_id.to_string(), persona_name.to_string());
+        let lock = self
+            .updating
+            .lock()
+            .expect("persona state lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = lock.lock().await;
+            update.await
+        };
+        drop(lock);
+
+        // Forget the lock unless another update is waiting on it
+        let mut updating = self.updating.lock().expect("persona state lock poisoned");
+        if updating
+            .get(&key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            updating.remove(&key);
+        }
+        result
+    }
+
+    async fn update_with_llm(&self, session_id: &str, exchange: &Exchange) -> Result<()> {
+        let mut state = self
+            .load(session_id, &exchange.persona_name, &exchange.config)
+            .await?;
+
+        let mut instructions = DELTA_INSTRUCTIONS.to_string();
+        if !exchange.config.moods.is_empty() {
+            let _ = write!(
+                instructions,
+                " The mood must be one of: {}.",
+                exchange.config.moods.join(", ")
+            );
+        }
+        let prompt = [
+            Message::system(&instructions),
+            Message::user(
+                &format!(
+                    "Current state: {}\n\nUser: {}\nCharacter: {}",
+                    serde_json::to_string(&state)?,
+                    exchange.input,
+                    exchange.reply
+                ),
+                None,
+            ),
+        ];
+        let options = GenerationOptions {
+            temperature: Some(DELTA_TEMPERATURE),
+            ..GenerationOptions::default()
+        };
+        let reply = exchange.llm.chat(&prompt, &options).await?;
+
+        let mut delta = parse_delta(&reply)?;
+        delta.affection = delta
+            .affection
+            .clamp(-MAX_LLM_AFFECTION_STEP, MAX_LLM_AFFECTION_STEP);
+        exchange.config.apply(&mut state, delta);
+
+        tracing::debug!(
+            "State of persona {} in session {} is now {:?}",
+            exchange.persona_name,
+            session_id,
+            state
+        );
+        self.save(session_id, &exchange.persona_name, state).await
+    }
+}
+
+fn state_key(persona_name: &str) -> String {
+    format!("{STATE_KEY_PREFIX}{persona_name}")
+}
+
+/// The JSON object in a model reply, which may be wrapped in prose or a code fence.
+fn parse_delta(reply: &str) -> Result<StateDelta> {
+    let start = reply
+        .find('{')
+        .context("State delta contains no JSON object")?;
+    let end = reply
+        .rfind('}')
+        .context("State delta contains no JSON object")?;
+    serde_json::from_str(reply.get(start..=end).unwrap_or_default())
+        .context(format!("Invalid state delta: {reply}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{llm::LlmError, memory::in_memory::InMemoryMemory};
+
+    /// Answers every request with the same text.
+    struct Reply(&'static str);
+
+    #[async_trait]
+    impl LLMClient for Reply {
+        async fn chat(&self, _: &[Message], _: &GenerationOptions) -> Result<String, LlmError> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    fn config(toml: &str) -> StateConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn state(mood: &str, affection: i32) -> PersonaState {
+        PersonaState {
+            mood: mood.to_string(),
+            affection,
+            custom: BTreeMap::new(),
+        }
+    }
+
+    const RULES: &str = r#"
+        min_affection = -10
+        max_affection = 10
+        moods = ["happy", "sad"]
+
+        [mood_prompts]
+        sad = "You are sad, {{user_name}}."
+
+        [[rules]]
+        keywords = ["Thanks", "thank you"]
+        affection = 3
+        mood = "happy"
+
+        [[rules]]
+        keywords = ["gift"]
+        affection = 4
+        set = { last_gift = "yes" }
+
+        [[rules]]
+        keywords = ["", " "]
+        affection = 100
+    "#;
+
+    #[test]
+    fn rules_combine_and_match_case_insensitively() {
+        let config = config(RULES);
+        assert!(config.rules_delta("hello there").is_none());
+
+        let delta = config.rules_delta("THANKS for the gift").unwrap();
+        assert_eq!(delta.affection, 7);
+        assert_eq!(delta.mood.as_deref(), Some("happy"));
+        assert_eq!(delta.custom["last_gift"], "yes");
+
+        let mut state = config.initial_state();
+        config.apply(&mut state, delta);
+        assert_eq!(state.mood, "happy");
+        assert_eq!(state.affection, 7);
+        assert_eq!(state.custom["last_gift"], "yes");
+    }
+
+    #[test]
+    fn affection_stays_within_bounds() {
+        let config = config(RULES);
+        let mut state = state("happy", 8);
+        config.apply(&mut state, config.rules_delta("thank you, a gift").unwrap());
+        assert_eq!(state.affection, 10);
+
+        config.apply(
+            &mut state,
+            StateDelta {
+                affection: i32::MIN,
+                ..StateDelta::default()
+            },
+        );
+        assert_eq!(state.affection, -10);
+
+        let config = self::config("initial_affection = 500");
+        assert_eq!(config.initial_state().affection, 100);
+    }
+
+    #[test]
+    fn ignores_moods_outside_the_list() {
+        let config = config(RULES);
+        let mut state = state("sad", 0);
+        config.apply(&mut state, parse_delta(r#"{"mood": "furious"}"#).unwrap());
+        assert_eq!(state.mood, "sad");
+        config.apply(&mut state, parse_delta(r#"{"mood": " "}"#).unwrap());
+        assert_eq!(state.mood, "sad");
+        config.apply(&mut state, parse_delta(r#"{"mood": "happy"}"#).unwrap());
+        assert_eq!(state.mood, "happy");
+    }
+
+    #[test]
+    fn parses_deltas_wrapped_in_prose() {
+        let reply = "Sure! ```json\n{\"mood\": null, \"affection\": -2, \
+                     \"custom\": {\"trust\": 3, \"nickname\": \"Al\"}}\n```";
+        let delta = parse_delta(reply).unwrap();
+        assert_eq!(delta.mood, None);
+        assert_eq!(delta.affection, -2);
+
+        let mut state = state("sad", 0);
+        config(RULES).apply(&mut state, delta);
+        assert_eq!(state.custom["trust"], "3");
+        assert_eq!(state.custom["nickname"], "Al");
+
+        // Missing fields change nothing
+        let delta = parse_delta("{}").unwrap();
+        assert_eq!((delta.mood, delta.affection), (None, 0));
+    }
+
+    #[test]
+    fn rejects_malformed_deltas() {
+        for reply in [
+            "",
+            "I feel happier now.",
+            "} backwards {",
+            "{\"affection\": \"a lot\"}",
+            "{\"affection\": 1.5}",
+            "{\"mood\": \"happy\",}",
+        ] {
+            assert!(parse_delta(reply).is_err(), "accepted {reply:?}");
+        }
+    }
+
+    #[test]
+    fn mood_prompt_follows_the_mood() {
+        let config = config(RULES);
+        assert_eq!(
+            config.mood_prompt(&state("sad", 0)),
+            Some("You are sad, {{user_name}}.")
+        );
+        assert_eq!(config.mood_prompt(&state("happy", 0)), None);
+    }
+
+    #[tokio::test]
+    async fn applies_llm_deltas_within_one_step() {
+        let tracker = StateTracker::new(None);
+        let exchange = |llm: Arc<dyn LLMClient>| Exchange {
+            persona_name: "p".to_string(),
+            config: config(RULES),
+            llm,
+            input: "hi".to_string(),
+            reply: "hello".to_string(),
+        };
+
+        let large = exchange(Arc::new(Reply(r#"Here: {"mood": "sad", "affection": 50}"#)));
+        tracker.update_with_llm("s", &large).await.unwrap();
+        let state = tracker.load("s", "p", &large.config).await.unwrap();
+        assert_eq!((state.mood.as_str(), state.affection), ("sad", 5));
+
+        // A malformed reply leaves the state alone
+        let broken = exchange(Arc::new(Reply("I'd say they are friends now")));
+        assert!(tracker.update_with_llm("s", &broken).await.is_err());
+        assert_eq!(tracker.load("s", "p", &broken.config).await.unwrap(), state);
+    }
+
+    #[tokio::test]
+    async fn serializes_updates_per_persona() {
+        let memory = Arc::new(InMemoryMemory::new(10, None).unwrap());
+        let tracker = StateTracker::new(Some(memory));
+        let config = config("");
+
+        // Both load before either saves, unless they are serialized
+        let bump = || {
+            tracker.serialized("s", "p", async {
+                let mut state = tracker.load("s", "p", &config).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                state.affection += 1;
+                tracker.save("s", "p", state).await.unwrap();
+            })
+        };
+        tokio::join!(bump(), bump());
+
+        assert_eq!(tracker.load("s", "p", &config).await.unwrap().affection, 2);
+        assert!(tracker.updating.lock().unwrap().is_empty());
+    }
+}
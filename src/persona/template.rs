@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+/// Replaces `{{name}}` placeholders in `template` with values from `vars`.
+/// A variable that is missing or empty renders as the fallback given in the
+/// placeholder (`{{user_name|friend}}`), or else as its default, so no raw
+/// braces reach the model.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+
+        let placeholder = &rest[start + 2..start + 2 + len];
+        let (name, fallback) = match placeholder.split_once('|') {
+            Some((name, fallback)) => (name.trim(), Some(fallback.trim())),
+            None => (placeholder.trim(), None),
+        };
+
+        if let Some(value) = vars.get(name).filter(|value| !value.is_empty()) {
+            output.push_str(value);
+        } else if let Some(fallback) = fallback {
+            output.push_str(fallback);
+        } else {
+            if !vars.contains_key(name) {
+                tracing::warn!("Unknown template variable '{}'", name);
+            }
+            output.push_str(default_value(name));
+        }
+
+        rest = &rest[start + 2 + len + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Value of a variable the context could not provide.
+fn default_value(name: &str) -> &'static str {
+    match name {
+        "user_name" => "User",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn substitutes_known_variables() {
+        let vars = vars(&[("user_name", "Alice"), ("persona.name", "Mia")]);
+        assert_eq!(
+            render("Hi {{user_name}}, I am {{ persona.name }}.", &vars),
+            "Hi Alice, I am Mia."
+        );
+    }
+
+    #[test]
+    fn falls_back_for_missing_or_empty_values() {
+        let vars = vars(&[("group_name", "")]);
+        assert_eq!(render("{{group_name|our group}}", &vars), "our group");
+        assert_eq!(render("{{user_name}}", &vars), "User");
+        assert_eq!(render("[{{unknown}}]", &vars), "[]");
+    }
+
+    #[test]
+    fn leaves_unclosed_braces_alone() {
+        assert_eq!(render("a {{b", &HashMap::new()), "a {{b");
+        assert_eq!(render("{{}}x", &HashMap::new()), "x");
+    }
+}
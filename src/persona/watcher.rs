@@ -0,0 +1,47 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::persona::PersonaManager;
+
+/// Editors emit several events per save; changes within this window cause a
+/// single reload.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+impl PersonaManager {
+    /// Reloads the personas whenever a file in the avatars directory (or a
+    /// fragment below it) changes.
+    /// Watching stops when the returned watcher is dropped.
+    pub fn watch(self: &Arc<Self>) -> Result<RecommendedWatcher> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = tx.send(());
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Persona watcher error: {}", e),
+            })?;
+        watcher.watch(&self.dir, RecursiveMode::Recursive)?;
+
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                tokio::time::sleep(DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                if let Err(e) = manager.reload() {
+                    tracing::error!("Failed to reload personas: {:#}", e);
+                }
+            }
+        });
+
+        tracing::info!("Watching {} for persona changes", self.dir.display());
+        Ok(watcher)
+    }
+}
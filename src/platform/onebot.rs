@@ -1,15 +1,35 @@
-use std::{env, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
-use futures::{SinkExt, StreamExt};
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use tracing::{error, info};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{self, protocol::Message};
+use tracing::{error, info, warn};
 
-use crate::{bot::Bot, platform::Platform, prompt::Input};
+use crate::{
+    bot::Bot,
+    llm::LlmError,
+    platform::Platform,
+    prompt::{Input, MessageContext},
+};
 
-pub struct OneBotPlatform;
+pub struct OneBotPlatform {
+    ws_url: String,
+}
+
+impl OneBotPlatform {
+    pub fn new(ws_url: &str) -> Self {
+        Self {
+            ws_url: ws_url.to_string(),
+        }
+    }
+}
 
 #[derive(Serialize)]
 struct SendMessageParams {
@@ -20,11 +40,33 @@ struct SendMessageParams {
 }
 
 #[derive(Serialize)]
-struct ApiCall {
+struct GroupInfoParams {
+    group_id: i64,
+}
+
+#[derive(Serialize)]
+struct ApiCall<P> {
     action: String,
-    params: SendMessageParams,
+    params: P,
+    /// Returned in the response, to match it with the call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    echo: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    echo: Option<String>,
+    data: Option<GroupInfo>,
 }
 
+#[derive(Deserialize)]
+struct GroupInfo {
+    group_name: Option<String>,
+}
+
+/// How long to wait for `get_group_info` before replying without the name.
+const GROUP_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
 // Minimal event structure
 #[derive(Deserialize, Debug)]
 struct Event {
@@ -37,91 +79,124 @@ struct Event {
     #[allow(dead_code)]
     message: Option<String>,
     raw_message: Option<String>,
-    #[allow(dead_code)]
     sender: Option<Sender>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Sender {
-    #[allow(dead_code)]
     nickname: Option<String>,
+    /// Group card (per-group display name), empty when not set.
+    card: Option<String>,
+}
+
+impl Sender {
+    fn display_name(self) -> Option<String> {
+        self.card
+            .filter(|card| !card.is_empty())
+            .or(self.nickname)
+            .filter(|name| !name.is_empty())
+    }
 }
 
 #[async_trait]
 impl Platform for OneBotPlatform {
     async fn run(&self, bot: Arc<Bot>) -> Result<()> {
-        let ws_url =
-            env::var("ONEBOT_WS_URL").unwrap_or_else(|_| "ws://127.0.0.1:6700".to_string());
-
-        info!("Connecting to OneBot at {}...", ws_url);
+        info!("Connecting to OneBot at {}...", self.ws_url);
 
-        let (ws_stream, _) = connect_async(&ws_url).await?;
+        let (ws_stream, _) = connect_async(&self.ws_url).await?;
         info!("Connected to OneBot!");
 
         let (mut write, mut read) = ws_stream.split();
+        // Events that arrived while waiting for an API response
+        let mut pending: VecDeque<String> = VecDeque::new();
+        // Message events carry no group name, so it is looked up once per group
+        let mut group_names: HashMap<i64, Option<String>> = HashMap::new();
 
-        while let Some(msg) = read.next().await {
-            let msg = match msg {
-                Ok(m) => m,
-                Err(e) => {
-                    error!("Error reading WS message: {}", e);
-                    continue;
+        loop {
+            let text = if let Some(text) = pending.pop_front() {
+                text
+            } else {
+                match read.next().await {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        error!("Error reading WS message: {}", e);
+                        continue;
+                    }
+                    None => break,
                 }
             };
 
-            if let Message::Text(text) = msg {
-                // Parse event
-                if let Ok(event) = serde_json::from_str::<Event>(&text) {
-                    // Filter for normal messages
-                    if event.post_type == "message" {
-                        let raw_msg = event.raw_message.unwrap_or_default();
-                        let user_id = event.user_id.unwrap_or(0);
-                        let group_id = event.group_id;
-                        let msg_type = event.message_type.unwrap_or("private".to_string());
-
-                        // Session ID: "onebot:private:123" or "onebot:group:456:123"
-                        let session_id = if let Some(gid) = group_id {
-                            format!("onebot:group:{gid}:{user_id}")
-                        } else {
-                            format!("onebot:private:{user_id}")
-                        };
-
-                        info!("Received message from {}: {}", session_id, raw_msg);
-
-                        // Process with Bot
-                        // Note: We might want to filter self-messages if the bridge echoes them,
-                        // but standard OneBot doesn't usually echo unless configured.
-
-                        match bot
-                            .handle_message(
-                                &session_id,
-                                Input::Text(raw_msg.clone()),
-                                Some(&user_id.to_string()),
-                            )
-                            .await
-                        {
-                            Ok(reply) => {
-                                // Send Reply
-                                let api_call = ApiCall {
-                                    action: "send_msg".to_string(),
-                                    params: SendMessageParams {
-                                        message_type: msg_type,
-                                        user_id: if group_id.is_none() {
-                                            Some(user_id)
-                                        } else {
-                                            None
-                                        }, // For private
-                                        group_id, // For group
-                                        message: reply,
-                                    },
-                                };
-
-                                let json = serde_json::to_string(&api_call)?;
-                                write.send(Message::Text(json)).await?;
+            // Parse event
+            if let Ok(event) = serde_json::from_str::<Event>(&text) {
+                // Filter for normal messages
+                if event.post_type == "message" {
+                    let raw_msg = event.raw_message.unwrap_or_default();
+                    let user_id = event.user_id.unwrap_or(0);
+                    let group_id = event.group_id;
+                    let msg_type = event.message_type.unwrap_or("private".to_string());
+
+                    // Session ID: "onebot:private:123" or "onebot:group:456:123"
+                    let session_id = if let Some(gid) = group_id {
+                        format!("onebot:group:{gid}:{user_id}")
+                    } else {
+                        format!("onebot:private:{user_id}")
+                    };
+
+                    info!("Received message from {}: {}", session_id, raw_msg);
+
+                    let group_name = match group_id {
+                        Some(gid) => {
+                            if let Entry::Vacant(entry) = group_names.entry(gid) {
+                                entry.insert(
+                                    fetch_group_name(&mut write, &mut read, &mut pending, gid)
+                                        .await?,
+                                );
                             }
-                            Err(e) => error!("Bot error: {}", e),
+                            group_names[&gid].clone()
                         }
-                    }
+                        None => None,
+                    };
+                    let ctx = MessageContext {
+                        user_id: Some(user_id.to_string()),
+                        user_name: event.sender.and_then(Sender::display_name),
+                        group_name,
+                        ..MessageContext::new("onebot")
+                    };
+
+                    // Process with Bot
+                    // Note: We might want to filter self-messages if the bridge echoes them,
+                    // but standard OneBot doesn't usually echo unless configured.
+
+                    let reply = match bot
+                        .handle_message(&session_id, Input::Text(raw_msg.clone()), &ctx)
+                        .await
+                    {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            error!("Bot error: {}", e);
+                            user_facing_error(&e).to_string()
+                        }
+                    };
+
+                    // Send Reply
+                    let api_call = ApiCall {
+                        action: "send_msg".to_string(),
+                        params: SendMessageParams {
+                            message_type: msg_type,
+                            user_id: if group_id.is_none() {
+                                Some(user_id)
+                            } else {
+                                None
+                            }, // For private
+                            group_id, // For group
+                            message: reply,
+                        },
+                        echo: None,
+                    };
+
+                    let json = serde_json::to_string(&api_call)?;
+                    write.send(Message::Text(json)).await?;
                 }
             }
         }
@@ -129,3 +204,94 @@ impl Platform for OneBotPlatform {
         Ok(())
     }
 }
+
+/// Asks for a group's name with `get_group_info`, queueing the events that
+/// arrive in the meantime. `None` if the implementation does not answer in time.
+async fn fetch_group_name<W, R>(
+    write: &mut W,
+    read: &mut R,
+    pending: &mut VecDeque<String>,
+    group_id: i64,
+) -> Result<Option<String>>
+where
+    W: Sink<Message, Error = tungstenite::Error> + Unpin,
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let echo = format!("get_group_info:{group_id}");
+    let api_call = ApiCall {
+        action: "get_group_info".to_string(),
+        params: GroupInfoParams { group_id },
+        echo: Some(echo.clone()),
+    };
+    write
+        .send(Message::Text(serde_json::to_string(&api_call)?))
+        .await?;
+
+    let response = tokio::time::timeout(GROUP_INFO_TIMEOUT, async {
+        while let Some(msg) = read.next().await {
+            let Ok(Message::Text(text)) = msg else {
+                continue;
+            };
+            match serde_json::from_str::<ApiResponse>(&text) {
+                Ok(response) if response.echo.as_deref() == Some(echo.as_str()) => {
+                    return Some(response);
+                }
+                _ => pending.push_back(text),
+            }
+        }
+        None
+    })
+    .await;
+
+    match response {
+        Ok(Some(response)) => Ok(response
+            .data
+            .and_then(|info| info.group_name)
+            .filter(|name| !name.is_empty())),
+        Ok(None) => Ok(None),
+        Err(_) => {
+            warn!("No reply to get_group_info for group {}", group_id);
+            Ok(None)
+        }
+    }
+}
+
+/// Text sent to the chat when a message could not be answered.
+fn user_facing_error(error: &anyhow::Error) -> &'static str {
+    error.downcast_ref::<LlmError>().map_or(
+        "Sorry, something went wrong. Please try again later.",
+        LlmError::user_message,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn group_name_lookup_queues_other_events() {
+        let (tx, mut sent) = futures::channel::mpsc::unbounded::<Message>();
+        let mut write = tx.sink_map_err(|_| tungstenite::Error::ConnectionClosed);
+        let event = r#"{"post_type":"message","raw_message":"hi"}"#;
+        let response = r#"{"status":"ok","retcode":0,"data":{"group_id":42,"group_name":"Book Club"},"echo":"get_group_info:42"}"#;
+        let mut read = futures::stream::iter([
+            Ok(Message::Text(event.to_string())),
+            Ok(Message::Text(response.to_string())),
+        ]);
+        let mut pending = VecDeque::new();
+
+        let name = fetch_group_name(&mut write, &mut read, &mut pending, 42)
+            .await
+            .unwrap();
+
+        assert_eq!(name.as_deref(), Some("Book Club"));
+        assert_eq!(pending, [event]);
+        let Some(Message::Text(call)) = sent.next().await else {
+            panic!("no API call sent");
+        };
+        let call: serde_json::Value = serde_json::from_str(&call).unwrap();
+        assert_eq!(call["action"], "get_group_info");
+        assert_eq!(call["params"]["group_id"], 42);
+        assert_eq!(call["echo"], "get_group_info:42");
+    }
+}
@@ -2,17 +2,25 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::StreamExt;
 use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
 
-use crate::{bot::Bot, platform::Platform, prompt::Input};
+use crate::{
+    bot::Bot,
+    platform::Platform,
+    prompt::{Input, MessageContext},
+};
+
+const SESSION_ID: &str = "terminal-session";
 
 pub struct TerminalPlatform;
 
 #[async_trait]
 impl Platform for TerminalPlatform {
     async fn run(&self, bot: Arc<Bot>) -> Result<()> {
-        println!("{}", bot.get_greeting());
-        println!("(Type 'exit' to quit)");
+        let ctx = MessageContext::new("terminal");
+        println!("{}", bot.get_greeting(SESSION_ID, &ctx).await?);
+        println!("(Type '/persona' to list personas, '/reset' to start over, 'exit' to quit)");
 
         let stdin = io::stdin();
         let mut reader = BufReader::new(stdin);
@@ -39,13 +47,25 @@ impl Platform for TerminalPlatform {
             }
 
             match bot
-                .handle_message("terminal-session", Input::Text(trimmed.to_string()), None)
+                .handle_message_stream(SESSION_ID, Input::Text(trimmed.to_string()), &ctx)
                 .await
             {
-                Ok(response) => {
-                    // Use stdout for response too
-                    let output = format!("{response}\n");
-                    stdout.write_all(output.as_bytes()).await?;
+                Ok(mut tokens) => {
+                    // Print each delta as soon as it arrives
+                    while let Some(token) = tokens.next().await {
+                        match token {
+                            Ok(delta) => {
+                                stdout.write_all(delta.as_bytes()).await?;
+                                stdout.flush().await?;
+                            }
+                            Err(e) => {
+                                let err_msg = format!("\nError: {e}");
+                                stdout.write_all(err_msg.as_bytes()).await?;
+                                break;
+                            }
+                        }
+                    }
+                    stdout.write_all(b"\n").await?;
                     stdout.flush().await?;
                 }
                 Err(e) => {
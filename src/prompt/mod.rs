@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Input {
@@ -8,12 +8,65 @@ pub enum Input {
     Video(String),  // URL or Path
 }
 
+/// Who sent a message and where, as far as the platform knows.
+#[derive(Debug, Clone)]
+pub struct MessageContext {
+    pub platform: &'static str,
+    pub user_id: Option<String>,
+    /// Display name of the sender.
+    pub user_name: Option<String>,
+    /// Name of the group chat, if the message was sent in one.
+    pub group_name: Option<String>,
+}
+
+impl MessageContext {
+    #[must_use]
+    pub fn new(platform: &'static str) -> Self {
+        Self {
+            platform,
+            user_id: None,
+            user_name: None,
+            group_name: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
+    // Assistant messages that only call tools come back with `"content": null`
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// A function call requested by the model (`OpenAI` `tool_calls` format).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON-encoded arguments, exactly as produced by the model.
+    pub arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 impl Message {
@@ -23,6 +76,8 @@ impl Message {
             role: role.to_string(),
             content: content.to_string(),
             user_id,
+            tool_calls: None,
+            tool_call_id: None,
         }
     }
 
@@ -40,4 +95,19 @@ impl Message {
     pub fn assistant(content: &str) -> Self {
         Self::new("assistant", content, None)
     }
+
+    /// Result of the tool call `tool_call_id`, fed back to the model.
+    #[must_use]
+    pub fn tool(tool_call_id: &str, content: &str) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new("tool", content, None)
+        }
+    }
+
+    /// Tool calls requested by the model, if any.
+    #[must_use]
+    pub fn requested_tool_calls(&self) -> &[ToolCall] {
+        self.tool_calls.as_deref().unwrap_or_default()
+    }
 }
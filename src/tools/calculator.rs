@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+
+use crate::tools::Tool;
+
+/// Longest expression accepted, in bytes; the model writes it and users can
+/// steer the model.
+const MAX_EXPRESSION_LEN: usize = 1024;
+
+/// Deepest nesting of parentheses, negations and powers. Each level recurses,
+/// so unbounded nesting could overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Evaluates arithmetic expressions so the model does not have to.
+pub struct Calculator;
+
+#[async_trait]
+impl Tool for Calculator {
+    fn name(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn description(&self) -> &'static str {
+        "Evaluate an arithmetic expression with + - * / % ^ and parentheses, e.g. (3 + 4) * 2.5"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": { "type": "string", "description": "The expression to evaluate" }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    async fn invoke(&self, arguments: Value) -> Result<String> {
+        let expression = arguments["expression"]
+            .as_str()
+            .context("Missing 'expression' argument")?;
+        let value = evaluate(expression)?;
+        Ok(value.to_string())
+    }
+}
+
+/// Recursive-descent evaluator:
+///
+/// ```text
+/// expr   := term (('+' | '-') term)*
+/// term   := factor (('*' | '/' | '%') factor)*
+/// factor := '-' factor | power
+/// power  := atom ('^' factor)?
+/// atom   := number | '(' expr ')'
+/// ```
+fn evaluate(expression: &str) -> Result<f64> {
+    if expression.len() > MAX_EXPRESSION_LEN {
+        anyhow::bail!("Expression is longer than {MAX_EXPRESSION_LEN} bytes");
+    }
+    let mut parser = Parser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos < parser.chars.len() {
+        anyhow::bail!(
+            "Unexpected '{}' at position {}",
+            parser.chars[parser.pos],
+            parser.pos
+        );
+    }
+    if !value.is_finite() {
+        anyhow::bail!("Result is not a finite number");
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expr(&mut self) -> Result<f64> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<f64> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = match op {
+                '*' => value * rhs,
+                '/' if rhs == 0.0 => anyhow::bail!("Division by zero"),
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<f64> {
+        // Every nesting level ('(', '-' and '^') passes through here
+        if self.depth == MAX_DEPTH {
+            anyhow::bail!("Expression is nested more than {MAX_DEPTH} levels deep");
+        }
+        self.depth += 1;
+        let value = if self.peek() == Some('-') {
+            self.pos += 1;
+            self.factor().map(|value| -value)
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        value
+    }
+
+    fn power(&mut self) -> Result<f64> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            // Right-associative, and binds tighter than negation: -2^2 == -(2^2)
+            let exponent = self.factor()?;
+            return Ok(base.powf(exponent));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expr()?;
+                if self.peek() != Some(')') {
+                    anyhow::bail!("Missing closing parenthesis");
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let literal: String = self.chars[start..self.pos].iter().collect();
+                literal
+                    .parse()
+                    .context(format!("Invalid number '{literal}'"))
+            }
+            Some(c) => anyhow::bail!("Unexpected '{c}' at position {}", self.pos),
+            None => anyhow::bail!("Unexpected end of expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value as the tool reports it.
+    fn eval(expression: &str) -> String {
+        evaluate(expression).unwrap().to_string()
+    }
+
+    #[test]
+    fn respects_precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3"), "7");
+        assert_eq!(eval("(1 + 2) * 3"), "9");
+        assert_eq!(eval("2 ^ 3 ^ 2"), "512");
+        assert_eq!(eval("-2 ^ 2"), "-4");
+        assert_eq!(eval("7 % 4 - 10 / 4"), "0.5");
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("1 + ").is_err());
+        assert!(evaluate("2 x 3").is_err());
+        assert!(evaluate("1.2.3").is_err());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH - 1)), "1");
+        assert!(evaluate(&nested(MAX_DEPTH)).is_err());
+        assert_eq!(eval(&format!("{}1", "-".repeat(MAX_DEPTH - 1))), "-1");
+        assert!(evaluate(&format!("{}1", "-".repeat(MAX_DEPTH))).is_err());
+        assert!(evaluate(&format!("{}1", "1^".repeat(MAX_DEPTH))).is_err());
+    }
+
+    #[test]
+    fn rejects_long_input() {
+        let long = "1+".repeat(MAX_EXPRESSION_LEN / 2) + "1";
+        assert!(evaluate(&long).is_err());
+        // Far beyond any limit, and would overflow the stack if parsed
+        assert!(evaluate(&"(".repeat(1_000_000)).is_err());
+    }
+}
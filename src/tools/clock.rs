@@ -0,0 +1,34 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Local, Utc};
+use serde_json::{Value, json};
+
+use crate::tools::Tool;
+
+/// Reports the current date and time.
+pub struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &'static str {
+        "current_time"
+    }
+
+    fn description(&self) -> &'static str {
+        "Get the current date, time and weekday of the server, in local time and UTC."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn invoke(&self, _arguments: Value) -> Result<String> {
+        let local = Local::now();
+        Ok(json!({
+            "local": local.format("%Y-%m-%d %H:%M:%S %:z").to_string(),
+            "weekday": local.format("%A").to_string(),
+            "utc": Utc::now().to_rfc3339(),
+        })
+        .to_string())
+    }
+}
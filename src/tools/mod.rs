@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::prompt::{Message, ToolCall};
+
+/// Names accepted by [`ToolRegistry::from_names`].
+pub const BUILTIN_TOOLS: [&str; 2] = ["time", "calculator"];
+
+/// A function the model can call while answering.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> Value;
+
+    async fn invoke(&self, arguments: Value) -> Result<String>;
+}
+
+/// Tool description advertised to the model.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a registry with the named built-in tools (see [`BUILTIN_TOOLS`]).
+    #[must_use]
+    pub fn from_names(names: &[String]) -> Self {
+        let mut registry = Self::new();
+
+        for name in names {
+            match name.as_str() {
+                "time" => registry.register(Arc::new(clock::CurrentTime)),
+                "calculator" => registry.register(Arc::new(calculator::Calculator)),
+                _ => tracing::warn!("Unknown tool '{}', ignoring", name),
+            }
+        }
+
+        registry
+    }
+
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.insert(tool.name().to_string(), tool);
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    #[must_use]
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .values()
+            .map(|tool| ToolDefinition {
+                name: tool.name().to_string(),
+                description: tool.description().to_string(),
+                parameters: tool.parameters(),
+            })
+            .collect()
+    }
+
+    /// Runs `call` and wraps the outcome in a `tool` message. Failures are
+    /// reported to the model as the tool result so it can recover.
+    pub async fn invoke(&self, call: &ToolCall) -> Message {
+        let result = match self.tools.get(&call.function.name) {
+            Some(tool) => match parse_arguments(&call.function.arguments) {
+                Ok(arguments) => tool.invoke(arguments).await,
+                Err(e) => Err(e),
+            },
+            None => Err(anyhow::anyhow!("Unknown tool: {}", call.function.name)),
+        };
+
+        let content = result.unwrap_or_else(|e| {
+            tracing::warn!("Tool '{}' failed: {}", call.function.name, e);
+            format!("Error: {e}")
+        });
+        Message::tool(&call.id, &content)
+    }
+}
+
+fn parse_arguments(raw: &str) -> Result<Value> {
+    if raw.trim().is_empty() {
+        return Ok(Value::Object(serde_json::Map::new()));
+    }
+    Ok(serde_json::from_str(raw)?)
+}
+
+pub mod calculator;
+pub mod clock;